@@ -0,0 +1,241 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::args::AccessLogFormat;
+
+/// Number of rotated access log files kept next to the current one
+const ROTATED_FILES: usize = 5;
+
+enum Output {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: File,
+        written: u64,
+        max_bytes: u64,
+    },
+}
+
+/// Writes one line per request, separately from the application logs
+pub struct AccessLog {
+    format: AccessLogFormat,
+    output: Mutex<Output>,
+}
+
+#[derive(Serialize)]
+struct AccessEntry<'a> {
+    timestamp: String,
+    remote_addr: String,
+    method: &'a str,
+    uri: &'a str,
+    version: String,
+    status: u16,
+    bytes: Option<u64>,
+    referer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    request_id: Option<&'a str>,
+    latency_ms: u64,
+}
+
+impl AccessLog {
+    /// Opens the access log at `target`, or stdout if it is "-". Files are rotated
+    /// once they grow past `max_mb` megabytes.
+    pub fn open(target: &str, format: AccessLogFormat, max_mb: u64) -> io::Result<Arc<AccessLog>> {
+        let output = match target {
+            "-" => Output::Stdout,
+            path => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Output::File {
+                    path: PathBuf::from(path),
+                    written: file.metadata()?.len(),
+                    file,
+                    max_bytes: max_mb * 1024 * 1024,
+                }
+            }
+        };
+
+        Ok(Arc::new(AccessLog {
+            format,
+            output: Mutex::new(output),
+        }))
+    }
+
+    fn write_entry(&self, entry: &AccessEntry) {
+        let line = match self.format {
+            AccessLogFormat::Combined => format!(
+                "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"\n",
+                entry.remote_addr,
+                entry.timestamp,
+                entry.method,
+                escape(entry.uri),
+                entry.version,
+                entry.status,
+                entry.bytes.map_or("-".to_string(), |b| b.to_string()),
+                entry.referer.map_or("-".to_string(), escape),
+                entry.user_agent.map_or("-".to_string(), escape),
+            ),
+            AccessLogFormat::Json => match serde_json::to_string(entry) {
+                Ok(json) => json + "\n",
+                Err(e) => {
+                    tracing::error!("Could not serialize access log entry: {e:?}");
+                    return;
+                }
+            },
+        };
+
+        let mut output = self.output.lock().unwrap();
+        if let Err(e) = output.write_line(line.as_bytes()) {
+            tracing::error!("Could not write to access log: {e:?}");
+        }
+    }
+}
+
+impl Output {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().lock().write_all(line),
+            Output::File {
+                path,
+                file,
+                written,
+                max_bytes,
+            } => {
+                if *max_bytes > 0 && *written + line.len() as u64 > *max_bytes {
+                    *file = rotate(path)?;
+                    *written = 0;
+                }
+                file.write_all(line)?;
+                *written += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shifts access.log -> access.log.1 -> access.log.2 ..., dropping the oldest, and
+/// opens a fresh file at `path`
+fn rotate(path: &PathBuf) -> io::Result<File> {
+    let rotated = |n: usize| PathBuf::from(format!("{}.{n}", path.display()));
+
+    for n in (1..ROTATED_FILES).rev() {
+        if rotated(n).exists() {
+            fs::rename(rotated(n), rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))?;
+
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Header value as text even when it isn't plain ASCII, which [`header_str`] leaves out
+fn header_text(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers.get(name).map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+}
+
+/// Escapes a request field for the combined format the way Apache does, so it can't break
+/// out of its quotes or forge lines: quotes and backslashes get a backslash, and other
+/// bytes that aren't printable ASCII are written as \xHH
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for byte in field.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{byte:02x}")),
+        }
+    }
+    escaped
+}
+
+/// Middleware writing every request to the access log once it has been answered
+pub async fn log_requests<B>(
+    State(access_log): State<Arc<AccessLog>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let timestamp = OffsetDateTime::now_utc();
+    let method = request.method().to_string();
+    let uri = request.uri().to_string();
+    let version = format!("{:?}", request.version());
+    let referer = header_text(request.headers(), header::REFERER);
+    let user_agent = header_text(request.headers(), header::USER_AGENT);
+
+    let response = next.run(request).await;
+
+    let timestamp = match access_log.format {
+        AccessLogFormat::Combined => timestamp.format(format_description!(
+            "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] +0000"
+        )),
+        AccessLogFormat::Json => timestamp.format(&time::format_description::well_known::Rfc3339),
+    }
+    .unwrap_or_default();
+
+    access_log.write_entry(&AccessEntry {
+        timestamp,
+        remote_addr: remote.ip().to_string(),
+        method: &method,
+        uri: &uri,
+        version,
+        status: response.status().as_u16(),
+        bytes: header_str(response.headers(), header::CONTENT_LENGTH).and_then(|l| l.parse().ok()),
+        referer: referer.as_deref(),
+        user_agent: user_agent.as_deref(),
+        request_id: header_str(response.headers(), header::HeaderName::from_static("x-request-id")),
+        latency_ms: started.elapsed().as_millis() as u64,
+    });
+
+    response
+}
+
+#[test]
+fn test_rotate() {
+    let dir = std::env::temp_dir().join(format!("cli_shortener_access_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("access.log");
+
+    let access_log = AccessLog::open(path.to_str().unwrap(), AccessLogFormat::Combined, 0).unwrap();
+    if let Output::File { max_bytes, .. } = &mut *access_log.output.lock().unwrap() {
+        *max_bytes = 10;
+    }
+
+    for _ in 0..3 {
+        access_log.output.lock().unwrap().write_line(b"12345678\n").unwrap();
+    }
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "12345678\n");
+    assert!(dir.join("access.log.1").exists());
+    assert!(dir.join("access.log.2").exists());
+    assert!(!dir.join("access.log.3").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("/happy-cat?q=1"), "/happy-cat?q=1");
+    assert_eq!(escape("curl \"x\" \\ y"), "curl \\\"x\\\" \\\\ y");
+    assert_eq!(escape("a\nb\tc"), "a\\x0ab\\x09c");
+    assert_eq!(escape("ü"), "\\xc3\\xbc");
+}
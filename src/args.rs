@@ -1,4 +1,8 @@
-use clap::{Args, Parser, Subcommand};
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::listener::ListenAddr;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -6,6 +10,10 @@ pub struct ClapArgs {
     #[clap(subcommand)]
     pub entity_type: EntityType,
 
+    /// Format of the command output. Errors always go to stderr
+    #[clap(short, long, value_enum, global = true, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+
     /// Increase verbosity level to see everything that's going on
     #[clap(short, long, action)]
     pub verbose: bool,
@@ -18,9 +26,151 @@ pub struct ClapArgs {
     #[clap(long, default_value = "127.0.0.1")]
     pub host: String,
 
+    /// Listen on this address instead of --host and --port, e.g. [::]:8080 or
+    /// unix:/run/links.sock. Can be given several times
+    #[clap(long)]
+    pub listen: Vec<ListenAddr>,
+
+    /// Fail to start if a port is in use instead of picking a random one
+    #[clap(long, action)]
+    pub strict_port: bool,
+
+    /// Manage links only through a Unix socket at this path, leaving TCP for redirects.
+    /// The CLI uses it to reach the server
+    #[clap(long)]
+    pub admin_socket: Option<PathBuf>,
+
+    /// Permissions of the admin socket in octal, deciding who can manage links
+    #[clap(long, default_value = "600", value_parser = parse_mode)]
+    pub admin_socket_mode: u32,
+
+    /// Manage links straight on storage, without a running server
+    #[clap(long, action)]
+    pub direct: bool,
+
     /// Set the pathname for the server
     #[clap(long, default_value = "")]
-    pub path: String
+    pub path: String,
+
+    /// Public address the server is reached at, e.g. https://go.example.com, used for the
+    /// links it hands out and their QR codes instead of the address it listens on
+    #[clap(long, value_parser = parse_public_url)]
+    pub public_url: Option<String>,
+
+    /// Format of the server logs
+    #[clap(long, value_enum, default_value_t = LogFormat::Compact)]
+    pub log_format: LogFormat,
+
+    /// Seconds to wait for in-flight requests when shutting down
+    #[clap(long, default_value_t = 10)]
+    pub drain_timeout: u64,
+
+    /// Write an access log of every request to this file, or "-" for stdout
+    #[clap(long)]
+    pub access_log: Option<String>,
+
+    /// Format of the access log
+    #[clap(long, value_enum, default_value_t = AccessLogFormat::Combined)]
+    pub access_log_format: AccessLogFormat,
+
+    /// Size in megabytes at which the access log file is rotated, 0 to never rotate
+    #[clap(long, default_value_t = 10)]
+    pub access_log_max_size: u64,
+
+    /// Export request spans to an OpenTelemetry collector, e.g. http://localhost:4318
+    #[cfg(feature = "otlp")]
+    #[clap(long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Shortcuts each client can create per minute, 0 for no limit
+    #[clap(long, default_value_t = 30)]
+    pub create_limit: u32,
+
+    /// Shortcuts each client can delete per minute, 0 for no limit
+    #[clap(long, default_value_t = 30)]
+    pub delete_limit: u32,
+
+    /// Redirects each client can follow per minute, 0 for no limit
+    #[clap(long, default_value_t = 600)]
+    pub redirect_limit: u32,
+
+    /// File of bearer tokens, one per line, whose requests are limited per token instead
+    /// of per IP address
+    #[clap(long)]
+    pub rate_limit_tokens: Option<PathBuf>,
+
+    /// Number of shortcuts cached in memory for redirects, 0 to disable
+    #[clap(long, default_value_t = 1000)]
+    pub cache_size: usize,
+
+    /// Seconds a cached shortcut is used before checking the db again
+    #[clap(long, default_value_t = 60)]
+    pub cache_ttl: u64,
+
+    /// Days deleted shortcuts can be restored before being purged, 0 to never purge
+    #[clap(long, default_value_t = 30)]
+    pub trash_days: u64,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    /// Tables and messages for people
+    Table,
+    /// A JSON object or list of objects
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// Tab separated values without a header, for shell scripts
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable single line logs
+    Compact,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum AccessLogFormat {
+    /// Apache combined log format
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// Parses an age like 45s, 90m, 12h, 30d or 2w
+fn parse_age(age: &str) -> Result<Duration, String> {
+    let error = || "expected an age like 45s, 90m, 12h, 30d or 2w".to_string();
+    let split = age.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let count: u64 = age[..split].parse().map_err(|_| error())?;
+    let unit = match &age[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(error()),
+    };
+
+    count.checked_mul(unit).map(Duration::from_secs).ok_or_else(error)
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err("expected permissions in octal like 600 or 660".to_string()),
+    }
+}
+
+fn parse_public_url(public_url: &str) -> Result<String, String> {
+    match url::Url::parse(public_url) {
+        Ok(url) if ["http", "https"].contains(&url.scheme()) && url.has_host() && url.query().is_none() => {
+            Ok(url.as_str().trim_end_matches('/').to_string())
+        }
+        _ => Err("expected an http or https address like https://go.example.com".to_string()),
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -31,20 +181,234 @@ pub enum EntityType {
     /// Delete a shortened link
     Delete(DeleteCommand),
 
+    /// Deletes all existing shortened links
+    Clear(ClearCommand),
+
     /// Lists all active shortened links
-    List,
+    List(ListCommand),
+
+    /// Changes the title, description or tags of a shortened link
+    Edit(EditCommand),
+
+    /// Finds shortened links by their hash, link, title, tags or description
+    Search(SearchCommand),
+
+    /// Lists deleted shortened links which can still be restored
+    Trash,
+
+    /// Restores a deleted shortened link
+    Restore(RestoreCommand),
+
+    /// Shows who created, edited or deleted shortened links, and when
+    Audit(AuditCommand),
+
+    /// Shows a QR code of a shortened link
+    Qr(QrCommand),
+
     /// Starts the web server which redirects the shortened links
-    Start,
+    Start(StartCommand),
+
+    /// Stops the web server running in the background
+    Stop,
+
+    /// Restarts the web server in the background
+    Restart,
+
+    /// Shows whether the web server is running and can reach its storage
+    Status,
+
+    /// Generates systemd units that run the web server with the current options
+    InstallService(InstallServiceCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct StartCommand {
+    /// Run the server in the background, logging to the state directory
+    #[clap(short, long, action)]
+    pub detach: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct InstallServiceCommand {
+    /// Install a system unit in /etc/systemd/system instead of a user unit
+    #[clap(long, action)]
+    pub system: bool,
+
+    /// User the system unit runs the server as. Defaults to the user running sudo, or else
+    /// a user systemd allocates when the server starts
+    #[clap(long, requires = "system")]
+    pub user: Option<String>,
+
+    /// Also generate a socket unit, so systemd opens the port and starts the server on demand
+    #[clap(long, action)]
+    pub socket: bool,
+
+    /// Print the units instead of writing them
+    #[clap(long, action)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Args)]
 pub struct NewCommand {
-    /// Link to be converted to a shortened link
-    pub link: String,
+    /// Links to be converted to shortened links, optionally with placeholders like {0} or {name}
+    #[clap(required_unless_present = "from_file")]
+    pub links: Vec<String>,
+
+    /// Also read links from a file, or stdin with -, one per line or as CSV with an alias column
+    #[clap(long, value_name = "FILE")]
+    pub from_file: Option<PathBuf>,
+
+    /// Remove tracking parameters such as utm_source and fbclid from the link
+    #[clap(long, action)]
+    pub strip_tracking: bool,
+
+    /// Forward extra path segments and query parameters, e.g. /gh/org/repo
+    #[clap(long, action)]
+    pub prefix: bool,
+
+    /// Require a password before the shortcut redirects, asked for without showing it
+    #[clap(long, action)]
+    pub password: bool,
+
+    /// Read the password from the first line of stdin instead of asking for it
+    #[clap(long, action)]
+    pub password_stdin: bool,
+
+    /// Short name for the link, shown when listing
+    #[clap(long)]
+    pub title: Option<String>,
+
+    /// Longer notes on what the link is for
+    #[clap(long = "desc", visible_alias = "description", value_name = "DESCRIPTION")]
+    pub description: Option<String>,
+
+    /// Tag the link, e.g. --tag infra. Can be given several times
+    #[clap(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+
+    /// Copy the shortened link to the clipboard
+    #[clap(long, action)]
+    pub copy: bool,
+
+    /// Show a QR code of the shortened link in the terminal
+    #[clap(long, action)]
+    pub qr: bool,
+
+    /// Also save the QR code as a .png or .svg image
+    #[clap(long)]
+    pub qr_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct QrCommand {
+    /// Shortened link, or just its hash
+    pub shortcut: String,
+
+    /// Save the QR code as a .png or .svg image
+    #[clap(long)]
+    pub file: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
 pub struct DeleteCommand {
-    /// Link to be converted to a shortened link
-    pub link: String,
+    /// Shortened link, or just its hash
+    #[clap(
+        required_unless_present_any = ["link", "pattern", "older_than"],
+        conflicts_with_all = ["link", "pattern", "older_than"]
+    )]
+    pub shortcut: Option<String>,
+
+    /// Delete every shortcut to this original link
+    #[clap(long)]
+    pub link: Option<String>,
+
+    /// Delete shortcuts whose hash matches a glob like "release-*"
+    #[clap(long = "match", value_name = "GLOB")]
+    pub pattern: Option<String>,
+
+    /// Delete shortcuts created longer ago than this, e.g. 30d, 12h or 2w
+    #[clap(long, value_name = "AGE", value_parser = parse_age)]
+    pub older_than: Option<Duration>,
+
+    /// Delete the matching shortcuts without asking first
+    #[clap(short, long, action)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ListCommand {
+    /// Only list links with this tag. Can be given several times to require them all
+    #[clap(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct EditCommand {
+    /// Shortened link, or just its hash
+    pub shortcut: String,
+
+    /// New title, or "" to remove it
+    #[clap(long)]
+    pub title: Option<String>,
+
+    /// New description, or "" to remove it
+    #[clap(long = "desc", visible_alias = "description", value_name = "DESCRIPTION")]
+    pub description: Option<String>,
+
+    /// Replace the tags with these. Can be given several times
+    #[clap(long = "tag", value_name = "TAG", conflicts_with = "clear_tags")]
+    pub tags: Vec<String>,
+
+    /// Remove all tags
+    #[clap(long, action)]
+    pub clear_tags: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchCommand {
+    /// Words to look for, matching the start of words too, e.g. kube finds kubernetes
+    #[clap(required = true)]
+    pub query: Vec<String>,
+
+    /// Most links to show, up to 100
+    #[clap(long, default_value_t = 20, value_parser = clap::value_parser!(u16).range(1..=100))]
+    pub limit: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreCommand {
+    /// Shortened link, or just its hash
+    pub shortcut: String,
+}
+
+#[derive(Debug, Args)]
+pub struct AuditCommand {
+    /// Only show changes to this shortened link, or just its hash
+    pub shortcut: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ClearCommand {
+    /// Delete every shortcut without asking first
+    #[clap(short, long, action)]
+    pub yes: bool,
+}
+
+#[test]
+fn test_parse_age() {
+    assert_eq!(parse_age("45s"), Ok(Duration::from_secs(45)));
+    assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+    assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+    for age in ["30", "d", "30y", "-1d", "1.5h", ""] {
+        assert!(parse_age(age).is_err());
+    }
+}
+
+#[test]
+fn test_parse_public_url() {
+    assert_eq!(parse_public_url("https://Go.Example.com/").unwrap(), "https://go.example.com");
+    assert_eq!(parse_public_url("https://example.com/links").unwrap(), "https://example.com/links");
+    for public_url in ["go.example.com", "ftp://example.com", "https://example.com/?a=b"] {
+        assert!(parse_public_url(public_url).is_err());
+    }
 }
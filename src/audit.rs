@@ -0,0 +1,102 @@
+use std::{convert::Infallible, env, net::SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+
+/// Header the CLI names its user and host with, e.g. alice@laptop. Anyone can send it,
+/// so it is only recorded as a claim next to the peer the server saw
+pub const USER_HEADER: &str = "x-links-user";
+
+/// Actor of changes the server makes by itself, such as purging the trash
+pub const SERVER_ACTOR: &str = "server";
+
+/// Longest claimed user kept, so a client can't fill the audit log with one header
+const MAX_CLAIM_LEN: usize = 100;
+
+/// Who made a request, recorded with the changes it makes. Clients are identified by the
+/// user id of their process on the admin socket, or else by IP address, followed by the
+/// user they claim to be, e.g. "uid:1000 claiming alice@laptop"
+pub struct Actor(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Actor, Infallible> {
+        #[cfg(unix)]
+        if let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<crate::listener::UnixPeer>>() {
+            let peer = match peer.uid {
+                Some(uid) => format!("uid:{uid}"),
+                None => "unix".to_string(),
+            };
+            return Ok(Actor(actor(&parts.headers, peer)));
+        }
+
+        let peer = match parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(remote)) => format!("ip:{}", remote.ip()),
+            None => "unknown".to_string(),
+        };
+        Ok(Actor(actor(&parts.headers, peer)))
+    }
+}
+
+fn actor(headers: &HeaderMap, peer: String) -> String {
+    let claimed = headers
+        .get(USER_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(str::trim)
+        .filter(|u| !u.is_empty());
+
+    match claimed {
+        Some(user) => {
+            let user = user.chars().filter(|c| !c.is_control()).take(MAX_CLAIM_LEN).collect::<String>();
+            format!("{peer} claiming {user}")
+        }
+        None => peer,
+    }
+}
+
+/// User and host running the CLI, e.g. alice@laptop
+pub fn local_user() -> String {
+    let user = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    match hostname() {
+        Some(host) => format!("{user}@{host}"),
+        None => user,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length, which gethostname won't write past
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return None;
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..len]).into_owned()).filter(|h| !h.is_empty())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    env::var("COMPUTERNAME").ok()
+}
+
+#[test]
+fn test_actors() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(actor(&headers, "ip:10.0.0.7".to_string()), "ip:10.0.0.7");
+
+    headers.insert(USER_HEADER, "alice@laptop".parse().unwrap());
+    assert_eq!(actor(&headers, "uid:1000".to_string()), "uid:1000 claiming alice@laptop");
+
+    // Tokens aren't verified, so they say nothing about who made the request
+    headers.insert(axum::http::header::AUTHORIZATION, "Bearer s3cr3t-t0k3n".parse().unwrap());
+    headers.insert(USER_HEADER, " ".parse().unwrap());
+    assert_eq!(actor(&headers, "ip:10.0.0.7".to_string()), "ip:10.0.0.7");
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+/// A link read from a batch file, with the line it came from for reporting
+#[derive(Debug, PartialEq)]
+pub struct BatchLine {
+    pub line: usize,
+    pub link: String,
+    pub alias: Option<String>,
+}
+
+/// Reads the whole file, or stdin if the path is "-"
+pub fn read(path: &Path) -> io::Result<String> {
+    match path.to_str() {
+        Some("-") => {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            Ok(text)
+        }
+        _ => fs::read_to_string(path),
+    }
+}
+
+/// Parses one link per line, or CSV rows of a link and an optional alias. Empty lines,
+/// lines starting with # and a header row naming the link column are skipped
+pub fn parse(text: &str) -> Vec<BatchLine> {
+    let mut lines = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = csv_fields(line).into_iter().map(|f| f.trim().to_string());
+        let link = fields.next().unwrap_or_default();
+        let alias = fields.next().filter(|a| !a.is_empty());
+
+        if lines.is_empty() && ["link", "url"].contains(&link.to_lowercase().as_str()) {
+            continue;
+        }
+
+        lines.push(BatchLine {
+            line: i + 1,
+            link,
+            alias,
+        });
+    }
+
+    lines
+}
+
+/// Splits a CSV row, allowing quoted fields with commas and doubled quotes in them
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (',', false) => fields.push(String::new()),
+            (c, _) => field.push(c),
+        }
+    }
+
+    fields
+}
+
+#[test]
+fn test_batch_lines() {
+    let text = "link,alias\n\
+                https://example.com/releases/1.0\n\
+                \n\
+                # not a link\n\
+                example.com/docs, docs\n\
+                \"https://example.com/?a=1,2\",\"say-\"\"hi\"\"\"\n";
+
+    assert_eq!(
+        parse(text),
+        vec![
+            BatchLine {
+                line: 2,
+                link: "https://example.com/releases/1.0".to_string(),
+                alias: None,
+            },
+            BatchLine {
+                line: 5,
+                link: "example.com/docs".to_string(),
+                alias: Some("docs".to_string()),
+            },
+            BatchLine {
+                line: 6,
+                link: "https://example.com/?a=1,2".to_string(),
+                alias: Some("say-\"hi\"".to_string()),
+            },
+        ]
+    );
+
+    assert_eq!(parse("https://example.com\n")[0].line, 1);
+    assert!(parse("\n# nothing\n").is_empty());
+}
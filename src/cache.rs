@@ -0,0 +1,127 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use aws_sdk_dynamodb::Client;
+use lru::LruCache;
+
+use crate::{
+    db::{self, DbError, Shortcut},
+    metrics::METRICS,
+};
+
+/// Read-through cache of recently opened shortcuts, so hot links skip the db
+pub struct ShortcutCache {
+    entries: Option<Mutex<LruCache<String, (Shortcut, Instant)>>>,
+    ttl: Duration,
+}
+
+impl ShortcutCache {
+    /// Creates a cache holding up to `capacity` shortcuts for `ttl`, or disabled if 0
+    pub fn new(capacity: usize, ttl: Duration) -> Arc<ShortcutCache> {
+        Arc::new(ShortcutCache {
+            entries: NonZeroUsize::new(capacity).map(|c| Mutex::new(LruCache::new(c))),
+            ttl,
+        })
+    }
+
+    /// Get the given hash's shortcut, only going to the db if not cached or expired
+    pub async fn get_shortcut(
+        &self,
+        client: &Client,
+        table_name: &str,
+        hash: &str,
+    ) -> Result<Shortcut, DbError> {
+        if let Some(shortcut) = self.lookup(hash) {
+            METRICS.cache_hits.inc();
+            tracing::debug!("Cache hit for shortcut with hash {hash}");
+            return Ok(shortcut);
+        }
+
+        METRICS.cache_misses.inc();
+        tracing::debug!("Cache miss for shortcut with hash {hash}");
+
+        let shortcut = db::get_shortcut(client, table_name, hash).await?;
+        self.insert(shortcut.clone());
+        Ok(shortcut)
+    }
+
+    /// Drops the hash's shortcut after it has been deleted or edited
+    pub fn invalidate(&self, hash: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(hash);
+        }
+    }
+
+    fn lookup(&self, hash: &str) -> Option<Shortcut> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+
+        match entries.get(hash) {
+            Some((shortcut, cached_at)) if cached_at.elapsed() < self.ttl => Some(shortcut.clone()),
+            Some(_) => {
+                entries.pop(hash);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, shortcut: Shortcut) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .unwrap()
+                .put(shortcut.hash.clone(), (shortcut, Instant::now()));
+        }
+    }
+}
+
+/// How long a count of the links is reported before counting again
+const LINK_COUNT_TTL: Duration = Duration::from_secs(60);
+
+/// Number of shortcuts not in the trash, which takes a scan of the whole table to count,
+/// so it is only counted again once it is a minute old
+#[derive(Default)]
+pub struct LinkCount {
+    counted: Mutex<Option<(i64, Instant)>>,
+}
+
+impl LinkCount {
+    pub async fn get(&self, client: &Client, table_name: &str) -> Result<i64, DbError> {
+        if let Some((count, counted_at)) = *self.counted.lock().unwrap() {
+            if counted_at.elapsed() < LINK_COUNT_TTL {
+                return Ok(count);
+            }
+        }
+
+        let count = db::count_shortcuts(client, table_name).await?;
+        *self.counted.lock().unwrap() = Some((count, Instant::now()));
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_cache_entries() {
+    let shortcut = Shortcut {
+        link: "https://www.google.com".to_string(),
+        hash: "hello-world".to_string(),
+        ..Shortcut::default()
+    };
+
+    let cache = ShortcutCache::new(1, Duration::from_secs(60));
+    cache.insert(shortcut.clone());
+    assert!(cache.lookup("hello-world").is_some());
+
+    cache.invalidate("hello-world");
+    assert!(cache.lookup("hello-world").is_none());
+
+    let expired = ShortcutCache::new(1, Duration::ZERO);
+    expired.insert(shortcut.clone());
+    assert!(expired.lookup("hello-world").is_none());
+
+    let disabled = ShortcutCache::new(0, Duration::from_secs(60));
+    disabled.insert(shortcut);
+    assert!(disabled.lookup("hello-world").is_none());
+}
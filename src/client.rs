@@ -0,0 +1,129 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use reqwest::{header, Method, StatusCode};
+use thiserror::Error;
+
+use crate::audit;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("could not reach the links server")]
+    ConnectionError(String),
+}
+
+/// Sends requests from the CLI to the local server, through its admin socket if it has one
+pub struct AdminClient {
+    addr: SocketAddr,
+    admin_socket: Option<PathBuf>,
+    path: String,
+    /// Sent with every request, so the server can tell who made a change
+    user: String,
+    /// Shared by all requests over TCP, so a batch of them reuses connections
+    http: reqwest::Client,
+}
+
+impl AdminClient {
+    pub fn new(addr: SocketAddr, admin_socket: Option<PathBuf>, path: &str) -> AdminClient {
+        AdminClient {
+            addr,
+            admin_socket,
+            path: path.to_string(),
+            user: audit::local_user(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Sends a request to `route` under the server path, "" being the path itself, and
+    /// returns the status and body of the response
+    pub async fn send(
+        &self,
+        method: Method,
+        route: &str,
+        json: Option<String>,
+    ) -> Result<(StatusCode, String), ClientError> {
+        let uri = match (self.path.as_str(), route) {
+            (path, "") => format!("/{path}"),
+            ("", route) => format!("/{route}"),
+            (path, route) => format!("/{path}/{route}"),
+        };
+
+        match &self.admin_socket {
+            Some(socket) => send_unix(socket, method, &uri, &self.user, json).await,
+            None => {
+                let mut request = self
+                    .http
+                    .request(method, format!("http://{}{uri}", self.addr))
+                    .header(audit::USER_HEADER, &self.user);
+                if let Some(json) = json {
+                    request = request.header(header::CONTENT_TYPE, "application/json").body(json);
+                }
+
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| ClientError::ConnectionError(e.to_string()))?;
+                let status = response.status();
+                let body = response
+                    .text()
+                    .await
+                    .map_err(|e| ClientError::ConnectionError(e.to_string()))?;
+                Ok((status, body))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn send_unix(
+    socket: &PathBuf,
+    method: Method,
+    uri: &str,
+    user: &str,
+    json: Option<String>,
+) -> Result<(StatusCode, String), ClientError> {
+    let connection_error = |e: &dyn std::error::Error| ClientError::ConnectionError(e.to_string());
+
+    let stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .map_err(|e| connection_error(&e))?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(|e| connection_error(&e))?;
+    tokio::spawn(connection);
+
+    let mut request = hyper::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::HOST, "localhost")
+        .header(audit::USER_HEADER, user);
+    if json.is_some() {
+        request = request.header(header::CONTENT_TYPE, "application/json");
+    }
+    let request = request
+        .body(hyper::Body::from(json.unwrap_or_default()))
+        .map_err(|e| connection_error(&e))?;
+
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| connection_error(&e))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| connection_error(&e))?;
+
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+#[cfg(not(unix))]
+async fn send_unix(
+    _socket: &PathBuf,
+    _method: Method,
+    _uri: &str,
+    _user: &str,
+    _json: Option<String>,
+) -> Result<(StatusCode, String), ClientError> {
+    Err(ClientError::ConnectionError(
+        "unix sockets are only supported on unix".to_string(),
+    ))
+}
@@ -0,0 +1,52 @@
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+/// Clipboard tools available in this session, each reading the text to copy from stdin
+fn providers() -> Vec<(&'static str, &'static [&'static str])> {
+    let mut providers: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        providers.push(("wl-copy", &[]));
+    }
+    if env::var_os("DISPLAY").is_some() {
+        providers.push(("xclip", &["-selection", "clipboard"]));
+        providers.push(("xsel", &["--clipboard", "--input"]));
+    }
+    if cfg!(target_os = "macos") {
+        providers.push(("pbcopy", &[]));
+    }
+    if cfg!(windows) {
+        providers.push(("clip", &[]));
+    }
+
+    providers
+}
+
+/// Puts the text on the system clipboard with the first tool that works, returning
+/// whether it was copied. Does nothing if no clipboard is available
+pub fn copy(text: &str) -> bool {
+    providers()
+        .into_iter()
+        .any(|(program, args)| run(program, args, text).is_ok())
+}
+
+fn run(program: &str, args: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("{program} failed"))),
+    }
+}
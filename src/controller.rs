@@ -1,70 +1,270 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Instant};
 
 use askama::Template;
 use aws_sdk_dynamodb::Client;
 use axum::{
-    extract::{self, Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Redirect},
+    extract::{self, ConnectInfo, Path, Query, RawQuery, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Extension, Form,
 };
 
 use crate::{
-    db::{self, Shortcut},
-    utils::{self, is_url, IndexTemplate},
+    audit::Actor,
+    cache::{LinkCount, ShortcutCache},
+    db::{self, DbError, Shortcut},
+    metrics::METRICS,
+    qr::{self, QrFormat, QrOptions},
+    search,
+    shortcuts::{self, ShortcutError},
+    utils::{self, FailedAttempts, IndexTemplate, PasswordTemplate},
 };
 
 pub async fn open_shortcut(
-    State((client, table_name, address, path)): State<(Client, String, SocketAddr, String)>,
-    Path(hash): Path<String>,
+    State((client, table_name, base_url, path)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Path(params): Path<HashMap<String, String>>,
+    RawQuery(query): RawQuery,
 ) -> impl IntoResponse {
-    match db::get_shortcut(&client, &table_name, &hash).await {
+    let hash = params.get("hash").cloned().unwrap_or_default();
+
+    let shortcut = match fetch_shortcut(&cache, &client, &table_name, &hash).await {
+        Ok(shortcut) => shortcut,
+        Err(reply) => return reply.into_response(),
+    };
+
+    if shortcut.is_protected() {
+        tracing::info!("Requested password for {}", utils::public_url(&base_url, &path, &hash));
+        return password_form(&base_url, &path, &hash, StatusCode::OK, "");
+    }
+
+    match destination(&shortcut, params.get("rest"), query.as_deref()) {
         Ok(link) => {
-            tracing::info!("Redirected http://{address}/{path}/{hash} to {link}");
+            METRICS.redirects.inc();
+            tracing::info!("Redirected {} to {link}", utils::public_url(&base_url, &path, &hash));
             Redirect::temporary(&link).into_response()
         }
+        Err(reply) => reply.into_response(),
+    }
+}
+
+pub async fn unlock_shortcut(
+    State((client, table_name, base_url, path)): State<(Client, String, String, String)>,
+    Extension(attempts): Extension<Arc<FailedAttempts>>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(params): Path<HashMap<String, String>>,
+    RawQuery(query): RawQuery,
+    Form(unlock): Form<utils::UnlockShortcut>,
+) -> impl IntoResponse {
+    let hash = params.get("hash").cloned().unwrap_or_default();
+
+    if attempts.is_locked(remote.ip(), &hash) {
+        tracing::error!("Too many failed password attempts for {hash} from {}", remote.ip());
+        return password_form(
+            &base_url,
+            &path,
+            &hash,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed attempts, try again later",
+        );
+    }
+
+    let shortcut = match fetch_shortcut(&cache, &client, &table_name, &hash).await {
+        Ok(shortcut) => shortcut,
+        Err(reply) => return reply.into_response(),
+    };
+
+    if let Some(password_hash) = &shortcut.password_hash {
+        if !utils::verify_password(&unlock.password, password_hash) {
+            attempts.record_failure(remote.ip(), &hash);
+            tracing::error!("Incorrect password for {hash} from {}", remote.ip());
+            return password_form(
+                &base_url,
+                &path,
+                &hash,
+                StatusCode::UNAUTHORIZED,
+                "Incorrect password",
+            );
+        }
+        attempts.clear(remote.ip(), &hash);
+    }
+
+    match destination(&shortcut, params.get("rest"), query.as_deref()) {
+        Ok(link) => {
+            METRICS.redirects.inc();
+            tracing::info!("Unlocked and redirected {} to {link}", utils::public_url(&base_url, &path, &hash));
+            Redirect::to(&link).into_response()
+        }
+        Err(reply) => reply.into_response(),
+    }
+}
+
+/// QR code image of the shortcut's public link, drawn as asked in the query
+pub async fn qr_code(
+    State((client, table_name, base_url, path)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Path(hash): Path<String>,
+    Query(options): Query<QrOptions>,
+) -> impl IntoResponse {
+    if let Err(reply) = fetch_shortcut(&cache, &client, &table_name, &hash).await {
+        return reply.into_response();
+    }
+
+    let url = utils::public_url(&base_url, &path, &hash);
+    let content_type = match options.format {
+        QrFormat::Png => "image/png",
+        QrFormat::Svg => "image/svg+xml",
+    };
+
+    match qr::image(&url, &options) {
+        Ok(image) => {
+            tracing::info!("Created QR code for {url}");
+            ([(header::CONTENT_TYPE, content_type)], image).into_response()
+        }
         Err(e) => {
+            tracing::error!("Could not create QR code for {url}: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not create QR code for given shortcut",
+            )
+                .into_response()
+        }
+    }
+}
+
+type ErrorReply = (StatusCode, &'static str);
+
+async fn fetch_shortcut(
+    cache: &ShortcutCache,
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+) -> Result<Shortcut, ErrorReply> {
+    let shortcut = cache.get_shortcut(client, table_name, hash).await.map_err(|e| match e {
+        DbError::NotFoundError(_) => {
+            tracing::info!("Requested unknown shortcut with {hash}");
+            (StatusCode::NOT_FOUND, "The given shortcut does not exist")
+        }
+        e => {
             tracing::error!("Could not get redirect for shortcut with {hash}: {e:?}");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Could not get original link for given shortcut",
             )
-                .into_response()
         }
+    })?;
+
+    match shortcut.is_deleted() {
+        true => {
+            tracing::info!("Requested deleted shortcut with {hash}");
+            Err((StatusCode::GONE, "The given shortcut has been deleted"))
+        }
+        false => Ok(shortcut),
     }
 }
 
-pub async fn create_new_shortcut(
-    State((client, table_name, address, path)): State<(Client, String, SocketAddr, String)>,
-    extract::Json(create_link): extract::Json<utils::CreateLink>,
-) -> impl IntoResponse {
+/// Works out where to redirect to, filling in templates and forwarding extra path for prefix links
+fn destination(shortcut: &Shortcut, rest: Option<&String>, query: Option<&str>) -> Result<String, ErrorReply> {
+    let hash = &shortcut.hash;
 
-    if !is_url(&create_link.link) {
-        tracing::error!(
-            "Could not verify that the provided link is a valid URL: {}",
-            create_link.link
-        );
-        return (
-            StatusCode::BAD_REQUEST,
-            "Invalid URL provided as link"
-        ).into_response();
+    if shortcut.is_template() {
+        let positional: Vec<&str> = rest
+            .map(|r| r.split('/').filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let named: Vec<(String, String)> = query
+            .map(|q| {
+                url::form_urlencoded::parse(q.as_bytes())
+                    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        return utils::expand_template(&shortcut.link, &positional, &named).map_err(|e| {
+            tracing::error!("Could not expand template for shortcut with {hash}: {e:?}");
+            (StatusCode::BAD_REQUEST, "Missing parameters for templated shortcut")
+        });
     }
 
-    let shortcut = Shortcut {
-        link: create_link.link,
-        hash: utils::gen_hash(),
+    if !shortcut.prefix {
+        return match rest {
+            Some(_) => Err((StatusCode::NOT_FOUND, "The given shortcut does not exist")),
+            None => Ok(shortcut.link.clone()),
+        };
+    }
+
+    utils::forward_url(&shortcut.link, rest.map(|r| r.as_str()), query).map_err(|e| {
+        tracing::error!("Could not forward path for shortcut with {hash}: {e:?}");
+        (StatusCode::BAD_REQUEST, "Could not forward path to original link")
+    })
+}
+
+fn password_form(
+    base_url: &str,
+    path: &str,
+    hash: &str,
+    status: StatusCode,
+    error: &str,
+) -> Response {
+    let template = PasswordTemplate {
+        url: utils::public_url(base_url, path, hash),
+        error: error.to_string(),
     };
 
-    match db::add_shortcut(&client, &table_name, &shortcut).await {
-        Ok(_) => {
-            tracing::info!("Created shortcut http://{address}/{path}/{}", shortcut.hash);
-            format!("http://{address}/{path}/{}", shortcut.hash).into_response()
-        }
+    match template.render() {
+        Ok(reply_html) => (status, Html(reply_html)).into_response(),
         Err(e) => {
+            tracing::error!("Could not render template: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not render template").into_response()
+        }
+    }
+}
+
+pub async fn create_new_shortcut(
+    State((client, table_name, base_url, path)): State<(Client, String, String, String)>,
+    Actor(actor): Actor,
+    extract::Json(create_link): extract::Json<utils::CreateLink>,
+) -> impl IntoResponse {
+    match shortcuts::create_shortcut(&client, &table_name, &create_link, &actor).await {
+        Ok(shortcut) => {
+            METRICS.creations.inc();
+            let url = utils::public_url(&base_url, &path, &shortcut.hash);
+            tracing::info!("Created shortcut {url}");
+            url.into_response()
+        }
+        Err(ShortcutError::LinkError(e)) => {
             tracing::error!(
-                "Could not create shortcut with hash {} from {}: {e:?}",
-                shortcut.hash,
-                shortcut.link
+                "Could not verify that the provided link is a valid URL: {}: {e:?}",
+                create_link.link
             );
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid URL provided as link"
+            ).into_response()
+        }
+        Err(ShortcutError::AliasError(alias)) => {
+            tracing::error!("Could not use {alias} as an alias");
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid alias provided for link"
+            ).into_response()
+        }
+        Err(ShortcutError::TakenError(alias)) => {
+            tracing::error!("Could not use {alias} as an alias as it is already taken");
+            (
+                StatusCode::CONFLICT,
+                "The given alias is already taken"
+            ).into_response()
+        }
+        Err(ShortcutError::MetadataError(e)) => {
+            tracing::error!("Could not use metadata for {}: {e}", create_link.link);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid metadata provided for link: {e}"),
+            ).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not create shortcut from {}: {e:?}", create_link.link);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Could not create shortcut for given link",
@@ -74,29 +274,63 @@ pub async fn create_new_shortcut(
     }
 }
 
-pub async fn get_all_shortcuts(
-    State((client, table_name, _, _)): State<(Client, String, SocketAddr, String)>,
+/// Creates shortcuts for a list of links, reporting the outcome of each one
+pub async fn create_new_shortcuts(
+    State((client, table_name, base_url, path)): State<(Client, String, String, String)>,
+    Actor(actor): Actor,
+    extract::Json(create_links): extract::Json<Vec<utils::CreateLink>>,
 ) -> impl IntoResponse {
-    match db::get_all_shortcuts(&client, &table_name).await {
-        Ok(items) => {
-            let shortcuts: Vec<Shortcut> = items
-                .iter()
-                .filter_map(|fields| {
-                    if (fields.get("link").is_none() || fields.get("link").unwrap().as_s().is_err())
-                        || (fields.get("link_hash").is_none()
-                            || fields.get("link_hash").unwrap().as_s().is_err())
-                    {
-                        return None;
-                    }
-                    Some(Shortcut {
-                        link: fields.get("link").unwrap().as_s().unwrap().to_string(),
-                        hash: fields.get("link_hash").unwrap().as_s().unwrap().to_string(),
-                    })
-                })
-                .collect();
+    if create_links.len() > shortcuts::MAX_BATCH_SIZE {
+        tracing::error!("Could not create a batch of {} shortcuts", create_links.len());
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Too many links given in one batch",
+        )
+            .into_response();
+    }
+
+    let created = shortcuts::create_shortcuts(&client, &table_name, &create_links, &actor).await;
+    let results = create_links
+        .iter()
+        .zip(created)
+        .map(|(create_link, created)| match created {
+            Ok(shortcut) => {
+                METRICS.creations.inc();
+                let url = utils::public_url(&base_url, &path, &shortcut.hash);
+                tracing::info!("Created shortcut {url}");
+                utils::BatchResult {
+                    link: shortcut.link,
+                    shortcut: Some(url),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                tracing::error!("Could not create shortcut from {}: {e:?}", create_link.link);
+                utils::BatchResult {
+                    link: create_link.link.to_string(),
+                    shortcut: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    axum::Json(results).into_response()
+}
 
+pub async fn get_all_shortcuts(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Query(filter): Query<utils::ListFilter>,
+) -> impl IntoResponse {
+    match shortcuts::list_shortcuts(&client, &table_name).await {
+        Ok(shortcuts) => {
             tracing::info!("Collected all shortcuts");
 
+            let tags = filter.tags();
+            let shortcuts = shortcuts
+                .into_iter()
+                .filter(|shortcut| shortcut.has_tags(&tags))
+                .collect::<Vec<_>>();
             axum::Json(shortcuts).into_response()
         }
         Err(e) => {
@@ -110,25 +344,67 @@ pub async fn get_all_shortcuts(
     }
 }
 
-pub async fn delete_shortcut(
-    State((client, table_name, _, _)): State<(Client, String, SocketAddr, String)>,
-    Path(hash): Path<String>,
+/// Shortcuts matching the query, best matches first
+pub async fn search_shortcuts(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Query(query): Query<utils::SearchQuery>,
 ) -> impl IntoResponse {
-
-    if let Err(e) = db::get_shortcut(&client, &table_name, &hash).await {
-        tracing::error!("Could not locate shortcut with {hash}: {e:?}");
+    let terms = search::query_terms(&query.q);
+    if terms.is_empty() {
+        tracing::error!("Could not search without any words in {:?}", query.q);
         return (
             StatusCode::BAD_REQUEST,
-            "The given shortcut does not exist",
+            "Give some words to search for",
         )
-            .into_response()
+            .into_response();
+    }
+    if terms.len() > search::MAX_QUERY_TERMS {
+        tracing::error!("Could not search for {} words in {:?}", terms.len(), query.q);
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Give at most {} words to search for", search::MAX_QUERY_TERMS),
+        )
+            .into_response();
     }
 
-    match db::delete_shortcut(&client, &table_name, &hash).await {
+    let limit = query.limit.unwrap_or(search::DEFAULT_LIMIT).clamp(1, search::MAX_LIMIT);
+    match shortcuts::search_shortcuts(&client, &table_name, &query.q, limit).await {
+        Ok(shortcuts) => {
+            tracing::info!("Found {} shortcuts for {:?}", shortcuts.len(), query.q);
+            axum::Json(shortcuts).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not search shortcuts: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not search shortcuts",
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn delete_shortcut(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Actor(actor): Actor,
+    Path(hash): Path<String>,
+) -> impl IntoResponse {
+    match shortcuts::delete_shortcut(&client, &table_name, &hash, &actor).await {
         Ok(_) => {
+            cache.invalidate(&hash);
+            METRICS.deletions.inc();
             tracing::info!("Deleted shortcut with hash {hash}");
             StatusCode::NO_CONTENT.into_response()
         }
+        Err(ShortcutError::NotFoundError(_)) => {
+            tracing::error!("Could not locate shortcut with {hash}");
+            (
+                StatusCode::BAD_REQUEST,
+                "The given shortcut does not exist",
+            )
+                .into_response()
+        }
         Err(e) => {
             tracing::error!("Could not delete shortcut with hash {hash}: {e:?}");
             (
@@ -140,8 +416,206 @@ pub async fn delete_shortcut(
     }
 }
 
+/// Changes the title, description or tags of a shortcut
+pub async fn edit_shortcut(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Actor(actor): Actor,
+    Path(hash): Path<String>,
+    extract::Json(edit_link): extract::Json<utils::EditLink>,
+) -> impl IntoResponse {
+    match shortcuts::edit_shortcut(&client, &table_name, &hash, &edit_link, &actor).await {
+        Ok(shortcut) => {
+            cache.invalidate(&hash);
+            tracing::info!("Edited shortcut with hash {hash}");
+            axum::Json(shortcut).into_response()
+        }
+        Err(ShortcutError::NotFoundError(_)) => {
+            tracing::error!("Could not locate shortcut with {hash}");
+            (
+                StatusCode::NOT_FOUND,
+                "The given shortcut does not exist".to_string(),
+            )
+                .into_response()
+        }
+        Err(ShortcutError::MetadataError(e)) => {
+            tracing::error!("Could not use metadata for {hash}: {e}");
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid metadata provided for link: {e}"),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not edit shortcut with hash {hash}: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not edit shortcut".to_string(),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Deletes every shortcut matching the query, or all of them when asked for with all=true
+pub async fn delete_shortcuts(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Actor(actor): Actor,
+    Query(filter): Query<utils::DeleteFilter>,
+) -> impl IntoResponse {
+    if filter.is_unfiltered() && !filter.all {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Give a filter, or all=true to delete every shortcut",
+        )
+            .into_response();
+    }
+
+    match shortcuts::delete_shortcuts(&client, &table_name, &filter, &actor).await {
+        Ok(deleted) => {
+            for shortcut in &deleted {
+                cache.invalidate(&shortcut.hash);
+            }
+            METRICS.deletions.inc_by(deleted.len() as u64);
+            tracing::info!("Deleted {} shortcuts", deleted.len());
+            axum::Json(deleted).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not delete shortcuts: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not delete shortcuts",
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn get_trash(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+) -> impl IntoResponse {
+    match shortcuts::list_trash(&client, &table_name).await {
+        Ok(shortcuts) => {
+            tracing::info!("Collected deleted shortcuts");
+            axum::Json(shortcuts).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not access deleted shortcuts: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not access deleted shortcuts",
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn restore_shortcut(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Extension(cache): Extension<Arc<ShortcutCache>>,
+    Actor(actor): Actor,
+    Path(hash): Path<String>,
+) -> impl IntoResponse {
+    match shortcuts::restore_shortcut(&client, &table_name, &hash, &actor).await {
+        Ok(shortcut) => {
+            cache.invalidate(&hash);
+            tracing::info!("Restored shortcut with hash {hash}");
+            axum::Json(shortcut).into_response()
+        }
+        Err(ShortcutError::NotFoundError(_)) => {
+            tracing::error!("Could not locate deleted shortcut with {hash}");
+            (
+                StatusCode::BAD_REQUEST,
+                "The given shortcut is not in the trash",
+            )
+                .into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not restore shortcut with hash {hash}: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not restore shortcut",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Changes made to shortcuts, newest first, of one shortcut if the query names it
+pub async fn get_audit_log(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Query(filter): Query<utils::AuditFilter>,
+) -> impl IntoResponse {
+    match shortcuts::audit_log(&client, &table_name, filter.hash.as_deref()).await {
+        Ok(events) => {
+            tracing::info!("Collected audit log");
+            axum::Json(events).into_response()
+        }
+        Err(e) => {
+            tracing::error!("Could not access audit log: {e:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not access audit log",
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+pub async fn readyz(
+    State((client, table_name, _, _)): State<(Client, String, String, String)>,
+    Extension(started): Extension<Instant>,
+    Extension(link_count): Extension<Arc<LinkCount>>,
+) -> impl IntoResponse {
+    let ready = match db::describe_table(&client, &table_name).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("Could not reach table {table_name}: {e:?}");
+            false
+        }
+    };
+    let link_count = match ready {
+        true => link_count
+            .get(&client, &table_name)
+            .await
+            .map_err(|e| tracing::error!("Could not count links in {table_name}: {e:?}"))
+            .ok(),
+        false => None,
+    };
+
+    let status = utils::ServerStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: started.elapsed().as_secs(),
+        backend: "dynamodb".to_string(),
+        ready,
+        link_count,
+    };
+
+    match status.ready {
+        true => (StatusCode::OK, axum::Json(status)).into_response(),
+        false => (StatusCode::SERVICE_UNAVAILABLE, axum::Json(status)).into_response(),
+    }
+}
+
+/// Whether the server can reach its storage, without the details readyz gives, for
+/// listeners which don't manage links
+pub async fn ready(State((client, table_name, _, _)): State<(Client, String, String, String)>) -> impl IntoResponse {
+    match db::describe_table(&client, &table_name).await {
+        Ok(()) => (StatusCode::OK, "ready"),
+        Err(e) => {
+            tracing::error!("Could not reach table {table_name}: {e:?}");
+            (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+        }
+    }
+}
+
 pub async fn index(
-    State((_client, _table_name, _, _)): State<(Client, String, SocketAddr, String)>,
+    State((_client, _table_name, _, _)): State<(Client, String, String, String)>,
 ) -> impl IntoResponse {
     let template = IndexTemplate { url: "/".to_string() };
 
@@ -0,0 +1,195 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DaemonError {
+    #[error("server is not running in the background")]
+    NotRunning(),
+
+    #[error("server is already running in the background")]
+    AlreadyRunning(u32),
+
+    #[error("could not signal the server")]
+    SignalError(String),
+
+    #[error("server did not stop in time")]
+    TimeoutError(u32),
+
+    #[error("server exited while starting ({0}), see its log")]
+    ExitedError(String),
+
+    #[error("could not manage server files")]
+    IoError(#[from] io::Error),
+}
+
+/// Directory holding the PID and log files, e.g. ~/.local/state/cli_shortener
+pub fn state_dir() -> io::Result<PathBuf> {
+    let dir = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(env::temp_dir)
+        .join("cli_shortener");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// How long a background server is given to start listening before it is reported as
+/// started anyway
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn pid_file() -> io::Result<PathBuf> {
+    Ok(state_dir()?.join("cli_shortener.pid"))
+}
+
+pub fn log_file() -> io::Result<PathBuf> {
+    Ok(state_dir()?.join("cli_shortener.log"))
+}
+
+/// Arguments the background server was started with, as a JSON list
+fn args_file() -> io::Result<PathBuf> {
+    Ok(state_dir()?.join("cli_shortener.args"))
+}
+
+/// Arguments the background server was last started with, so it restarts the same way
+pub fn saved_args() -> Option<Vec<String>> {
+    serde_json::from_str(&fs::read_to_string(args_file().ok()?).ok()?).ok()
+}
+
+/// PID of the background server, if its PID file exists and the process is alive
+pub fn running_pid() -> Option<u32> {
+    let pid = fs::read_to_string(pid_file().ok()?)
+        .ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+    is_alive(pid).then_some(pid)
+}
+
+/// Runs this executable again with the given arguments in the background, sending its
+/// output to the log file and recording its PID and arguments. Waits until `listening`
+/// says the server is up, failing if it exits first
+pub fn spawn_detached(args: &[String], listening: impl Fn() -> bool) -> Result<u32, DaemonError> {
+    if let Some(pid) = running_pid() {
+        return Err(DaemonError::AlreadyRunning(pid));
+    }
+
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file()?)?;
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Own process group so Ctrl+C in the terminal doesn't reach the server
+        command.process_group(0);
+    }
+
+    let mut child = command.spawn()?;
+    fs::write(pid_file()?, child.id().to_string())?;
+    fs::write(args_file()?, serde_json::to_string(args).unwrap_or_default())?;
+
+    let started = Instant::now();
+    while !listening() && started.elapsed() < STARTUP_TIMEOUT {
+        if let Some(status) = child.try_wait()? {
+            fs::remove_file(pid_file()?).ok();
+            return Err(DaemonError::ExitedError(status.to_string()));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Ok(child.id())
+}
+
+/// Sends SIGTERM to the background server and waits for it to exit
+pub fn stop(timeout: Duration) -> Result<u32, DaemonError> {
+    let pid = match running_pid() {
+        Some(pid) => pid,
+        None => {
+            fs::remove_file(pid_file()?).ok();
+            return Err(DaemonError::NotRunning());
+        }
+    };
+
+    terminate(pid)?;
+
+    let started = Instant::now();
+    while is_alive(pid) {
+        if started.elapsed() > timeout {
+            return Err(DaemonError::TimeoutError(pid));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    fs::remove_file(pid_file()?).ok();
+    Ok(pid)
+}
+
+/// Removes the PID file if it belongs to this process, called as the server stops
+pub fn remove_pid_file() {
+    if let Ok(path) = pid_file() {
+        let is_own = fs::read_to_string(&path)
+            .map(|pid| pid.trim() == std::process::id().to_string())
+            .unwrap_or(false);
+        if is_own {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    // Signal 0 only checks that the process exists
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) == 0 };
+    exists && is_server(pid)
+}
+
+/// Checks the process runs this executable, so a PID file left by a crash or reboot
+/// doesn't point at whatever process has its PID now. Without /proc, any process counts
+#[cfg(unix)]
+fn is_server(pid: u32) -> bool {
+    if !std::path::Path::new("/proc/self/exe").exists() {
+        return true;
+    }
+    // A rebuilt executable is shown with " (deleted)" after its path
+    let exe = |path: PathBuf| path.to_string_lossy().trim_end_matches(" (deleted)").to_string();
+    match (fs::read_link(format!("/proc/{pid}/exe")), env::current_exe()) {
+        (Ok(process), Ok(current)) => exe(process) == exe(current),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), DaemonError> {
+    match unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } {
+        0 => Ok(()),
+        _ => Err(DaemonError::SignalError(io::Error::last_os_error().to_string())),
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) -> Result<(), DaemonError> {
+    Err(DaemonError::SignalError(
+        "stopping the server is only supported on unix".to_string(),
+    ))
+}
@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_dynamodb::{types::AttributeValue, Client};
+use aws_sdk_dynamodb::{
+    types::{AttributeValue, KeysAndAttributes, ReturnValue, Select},
+    Client,
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::task::JoinSet;
 
+use crate::{metrics, search, utils};
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum DbError {
     #[error("db request failed")]
@@ -15,12 +22,310 @@ pub enum DbError {
 
     #[error("duplicate data exists")]
     DuplicationError(),
+
+    #[error("no such data exists")]
+    NotFoundError(String),
+}
+
+impl DbError {
+    /// Short name of the error kind, used as a metrics label
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            DbError::RequestError(_) => "request",
+            DbError::RetrievalError(_) => "retrieval",
+            DbError::DuplicationError() => "duplication",
+            DbError::NotFoundError(_) => "not_found",
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Shortcut {
     pub link: String,
     pub hash: String,
+
+    /// Forward any extra path segments and query parameters onto the link
+    #[serde(default)]
+    pub prefix: bool,
+
+    /// Argon2 hash of the password needed to follow the shortcut
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+
+    /// Unix time in seconds when the shortcut was created, unknown for older shortcuts
+    #[serde(default)]
+    pub created_at: Option<u64>,
+
+    /// Unix time in seconds when the shortcut was last edited, deleted or restored
+    #[serde(default)]
+    pub updated_at: Option<u64>,
+
+    /// Who created the shortcut, see [`crate::audit::Actor`]
+    #[serde(default)]
+    pub created_by: Option<String>,
+
+    /// Unix time in seconds when the shortcut was moved to the trash
+    #[serde(default)]
+    pub deleted_at: Option<u64>,
+
+    #[serde(default)]
+    pub title: Option<String>,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Lowercase labels for grouping shortcuts, e.g. infra
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Shortcut {
+    /// Builds a shortcut from a db item, ignoring items without a valid link and hash.
+    /// Purged shortcuts are kept without a link, so their hash is never handed out again
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Shortcut> {
+        let hash = item.get("link_hash")?.as_s().ok()?;
+        let deleted_at = item
+            .get("deleted_at")
+            .and_then(|d| d.as_n().ok())
+            .and_then(|d| d.parse().ok());
+        let link = match (item.get("link").and_then(|l| l.as_s().ok()), deleted_at) {
+            (Some(link), _) => link.as_str(),
+            (None, Some(_)) => "",
+            (None, None) => return None,
+        };
+        let prefix = item
+            .get("prefix")
+            .and_then(|p| p.as_bool().ok())
+            .copied()
+            .unwrap_or(false);
+        let password_hash = item
+            .get("password_hash")
+            .and_then(|p| p.as_s().ok())
+            .cloned();
+        let created_at = item
+            .get("created_at")
+            .and_then(|c| c.as_n().ok())
+            .and_then(|c| c.parse().ok());
+        let updated_at = item
+            .get("updated_at")
+            .and_then(|u| u.as_n().ok())
+            .and_then(|u| u.parse().ok());
+        let created_by = item.get("created_by").and_then(|c| c.as_s().ok()).cloned();
+        let title = item.get("title").and_then(|t| t.as_s().ok()).cloned();
+        let description = item.get("description").and_then(|d| d.as_s().ok()).cloned();
+        let mut tags = item
+            .get("tags")
+            .and_then(|t| t.as_ss().ok())
+            .cloned()
+            .unwrap_or_default();
+        tags.sort();
+
+        Some(Shortcut {
+            link: link.to_string(),
+            hash: hash.to_string(),
+            prefix,
+            password_hash,
+            created_at,
+            updated_at,
+            created_by,
+            deleted_at,
+            title,
+            description,
+            tags,
+        })
+    }
+
+    /// Builds the db item storing the shortcut
+    pub fn to_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::from([
+            ("link".to_string(), AttributeValue::S(self.link.to_string())),
+            ("link_hash".to_string(), AttributeValue::S(self.hash.to_string())),
+            ("prefix".to_string(), AttributeValue::Bool(self.prefix)),
+        ]);
+
+        if let Some(password_hash) = &self.password_hash {
+            item.insert("password_hash".to_string(), AttributeValue::S(password_hash.to_string()));
+        }
+        if let Some(created_at) = self.created_at {
+            item.insert("created_at".to_string(), AttributeValue::N(created_at.to_string()));
+        }
+        if let Some(updated_at) = self.updated_at {
+            item.insert("updated_at".to_string(), AttributeValue::N(updated_at.to_string()));
+        }
+        if let Some(created_by) = &self.created_by {
+            item.insert("created_by".to_string(), AttributeValue::S(created_by.to_string()));
+        }
+        if let Some(deleted_at) = self.deleted_at {
+            item.insert("deleted_at".to_string(), AttributeValue::N(deleted_at.to_string()));
+        }
+        if let Some(title) = &self.title {
+            item.insert("title".to_string(), AttributeValue::S(title.to_string()));
+        }
+        if let Some(description) = &self.description {
+            item.insert("description".to_string(), AttributeValue::S(description.to_string()));
+        }
+        // String sets can't be empty, so shortcuts without tags leave the attribute out
+        if !self.tags.is_empty() {
+            item.insert("tags".to_string(), AttributeValue::Ss(self.tags.clone()));
+        }
+        let search_terms = search::terms(self);
+        if !search_terms.is_empty() && !self.is_purged() {
+            item.insert("search_terms".to_string(), AttributeValue::Ss(search_terms));
+        }
+
+        item
+    }
+
+    /// Checks if the shortcut has every one of the tags
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// Checks if the shortcut is in the trash, or was purged from it
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    /// Checks if the shortcut was purged from the trash, leaving only its hash behind
+    pub fn is_purged(&self) -> bool {
+        self.is_deleted() && self.link.is_empty()
+    }
+
+    /// Checks if the link has placeholders to fill in on redirect
+    pub fn is_template(&self) -> bool {
+        utils::is_template(&self.link)
+    }
+
+    /// Checks if a password must be given before redirecting
+    pub fn is_protected(&self) -> bool {
+        self.password_hash.is_some()
+    }
+}
+
+/// Changes recorded in the audit log
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Edit,
+    Delete,
+    Restore,
+    Purge,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Edit => "edit",
+            AuditAction::Delete => "delete",
+            AuditAction::Restore => "restore",
+            AuditAction::Purge => "purge",
+        }
+    }
+
+    fn parse(action: &str) -> Option<AuditAction> {
+        [
+            AuditAction::Create,
+            AuditAction::Edit,
+            AuditAction::Delete,
+            AuditAction::Restore,
+            AuditAction::Purge,
+        ]
+        .into_iter()
+        .find(|a| a.as_str() == action)
+    }
+}
+
+/// One change made to a shortcut, and who made it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEvent {
+    pub hash: String,
+
+    /// Unix time in seconds of the change
+    pub at: u64,
+
+    pub action: AuditAction,
+
+    pub actor: String,
+
+    /// What changed, e.g. the link that was shortened or the fields that were edited
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+/// Prefix of the items holding the audit log: a counter of the events of each hash at
+/// audit#<hash>, and every event in its own item at audit#<hash>#<seq>. Hashes can't
+/// contain #, so these never clash with shortcuts
+const AUDIT_PREFIX: &str = "audit#";
+
+impl AuditEvent {
+    /// Builds an event from its item, along with its place in the log of its hash. Other
+    /// items, including the counters, give None
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Option<(u64, AuditEvent)> {
+        let key = item.get("link_hash")?.as_s().ok()?.strip_prefix(AUDIT_PREFIX)?;
+        let (hash, seq) = key.rsplit_once('#')?;
+        let seq = seq.parse().ok()?;
+        let at = item.get("at")?.as_n().ok()?.parse().ok()?;
+        let action = AuditAction::parse(item.get("action")?.as_s().ok()?)?;
+        let actor = item.get("actor")?.as_s().ok()?;
+        let detail = item.get("detail").and_then(|d| d.as_s().ok()).cloned();
+
+        Some((
+            seq,
+            AuditEvent {
+                hash: hash.to_string(),
+                at,
+                action,
+                actor: actor.to_string(),
+                detail,
+            },
+        ))
+    }
+
+    /// Builds the item storing the event as number `seq` in the log of its hash
+    fn to_item(&self, seq: u64) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::from([
+            ("link_hash".to_string(), audit_key(&self.hash, seq)),
+            ("at".to_string(), AttributeValue::N(self.at.to_string())),
+            ("action".to_string(), AttributeValue::S(self.action.as_str().to_string())),
+            ("actor".to_string(), AttributeValue::S(self.actor.to_string())),
+        ]);
+        if let Some(detail) = &self.detail {
+            item.insert("detail".to_string(), AttributeValue::S(detail.to_string()));
+        }
+        item
+    }
+}
+
+/// Key of event number `seq` in the audit log of the hash
+fn audit_key(hash: &str, seq: u64) -> AttributeValue {
+    AttributeValue::S(format!("{AUDIT_PREFIX}{hash}#{seq}"))
+}
+
+/// Prefix of the items of the search index, holding the hashes of the shortcuts with each
+/// search term at search#<term>. Terms are single words, so these never clash either
+const SEARCH_PREFIX: &str = "search#";
+
+/// Most hashes listed for one search term, keeping its item well below DynamoDB's 400KB
+/// limit. A term with more is marked too common and no longer narrows down searches
+const MAX_TERM_HASHES: usize = 5000;
+
+/// Items updated at once when a shortcut's search terms change or shortcuts are deleted
+/// in bulk
+const MAX_CONCURRENT_UPDATES: usize = 16;
+
+/// Scan filter for shortcuts, leaving out the audit log and the search index
+const SHORTCUTS_FILTER: &str = "NOT begins_with(link_hash, :audit) AND NOT begins_with(link_hash, :search)";
+
+/// Checks if the key is of an audit log or search index item rather than a shortcut
+fn is_internal_key(key: &str) -> bool {
+    key.starts_with(AUDIT_PREFIX) || key.starts_with(SEARCH_PREFIX)
+}
+
+/// Key of the search index item of the term
+fn search_key(term: &str) -> HashMap<String, AttributeValue> {
+    HashMap::from([("link_hash".to_string(), AttributeValue::S(format!("{SEARCH_PREFIX}{term}")))])
 }
 
 pub async fn init_db_client() -> Client {
@@ -33,130 +338,742 @@ pub async fn init_db_client() -> Client {
     Client::new(&config)
 }
 
-/// Add link with generated hash to db
+/// Add link with generated hash to db, unless the hash was ever used, even if deleted since
 pub async fn add_shortcut(
     client: &Client,
     table_name: &str,
     shortcut: &Shortcut,
 ) -> Result<(), DbError> {
-    let link_av = AttributeValue::S(shortcut.link.to_string());
-    let hash_av = AttributeValue::S(shortcut.hash.to_string());
+    metrics::track_db("add_shortcut", async {
+        let request = client
+            .put_item()
+            .table_name(table_name)
+            .condition_expression("attribute_not_exists(link_hash)")
+            .set_item(Some(shortcut.to_item()));
 
-    if get_shortcut(client, table_name, &shortcut.hash)
-        .await
-        .is_ok()
-    {
-        return Err(DbError::DuplicationError());
+        tracing::debug!("Executing request [{request:?}] to add shortcut to db");
+
+        request.send().await.map_err(|e| {
+            match e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) {
+                true => DbError::DuplicationError(),
+                false => DbError::RequestError(e.to_string()),
+            }
+        })?;
+
+        tracing::debug!(
+            "Added link {} with hash {} to db",
+            shortcut.link,
+            shortcut.hash
+        );
+
+        Ok(())
+    })
+    .await
+}
+
+/// Get the given hash's shortcut from db
+pub async fn get_shortcut(
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+) -> Result<Shortcut, DbError> {
+    if is_internal_key(hash) {
+        return Err(DbError::NotFoundError(format!("No shortcut with {hash}")));
     }
 
-    let request = client
-        .put_item()
-        .table_name(table_name)
-        .item("link", link_av)
-        .item("link_hash", hash_av);
+    metrics::track_db("get_shortcut", async {
+        let request = client
+            .query()
+            .table_name(table_name)
+            .key_condition_expression("link_hash = :hash")
+            .expression_attribute_values(":hash", AttributeValue::S(hash.to_string()));
 
-    tracing::debug!("Executing request [{request:?}] to add shortcut to db");
+        tracing::debug!("Executing request [{request:?}] to get shortcut from db using hash");
 
-    request
-        .send()
-        .await
-        .map_err(|e| DbError::RequestError(e.to_string()))?;
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+        match response.items {
+            None => Err(DbError::RetrievalError(
+                "Query response did not have any items to check".to_string(),
+            )),
+            Some(items) => match items.len() {
+                1 => {
+                    match Shortcut::from_item(&items[0]) {
+                        None => Err(DbError::RetrievalError(
+                            "Query response item did not provide a valid link".to_string(),
+                        )),
+                        Some(shortcut) => {
+                            tracing::debug!("Fetched link {} from hash {hash}", shortcut.link);
+                            Ok(shortcut)
+                        }
+                    }
+                }
+                0 => Err(DbError::NotFoundError(format!("No shortcut with {hash}"))),
+                l => Err(DbError::RetrievalError(format!(
+                    "Query had {l} entries instead of one"
+                ))),
+            },
+        }
+    })
+    .await
+}
+
+pub async fn get_all_shortcuts(
+    client: &Client,
+    table_name: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    metrics::track_db("get_all_shortcuts", async {
+        scan(client, table_name, SHORTCUTS_FILTER).await
+    })
+    .await
+}
+
+/// Get the shortcuts not in the trash which the search index lists under every one of the
+/// terms, leaving the caller to rank them. Terms too common to be listed are skipped, and
+/// if all of them are, the shortcuts storing every term are scanned for instead
+pub async fn search_shortcuts(
+    client: &Client,
+    table_name: &str,
+    terms: &[String],
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    metrics::track_db("search_shortcuts", async {
+        let keys = terms.iter().map(|term| search_key(term)).collect::<Vec<_>>();
+        let index = batch_get(client, table_name, &keys).await?;
+
+        let mut candidates: Option<HashSet<String>> = None;
+        for key in &keys {
+            let item = index.iter().find(|item| item.get("link_hash") == key.get("link_hash"));
+            if item.is_some_and(|item| item.contains_key("too_common")) {
+                continue;
+            }
+            let hashes = item
+                .and_then(|item| item.get("hashes"))
+                .and_then(|h| h.as_ss().ok())
+                .map(|h| h.iter().cloned().collect::<HashSet<_>>())
+                .unwrap_or_default();
+            candidates = Some(match candidates {
+                None => hashes,
+                Some(candidates) => candidates.intersection(&hashes).cloned().collect(),
+            });
+        }
+
+        let Some(candidates) = candidates else {
+            return scan_terms(client, table_name, terms).await;
+        };
+        let keys = candidates
+            .into_iter()
+            .map(|hash| HashMap::from([("link_hash".to_string(), AttributeValue::S(hash))]))
+            .collect::<Vec<_>>();
+        let items = batch_get(client, table_name, &keys).await?;
+        Ok(items.into_iter().filter(|item| !item.contains_key("deleted_at")).collect())
+    })
+    .await
+}
 
-    tracing::debug!(
-        "Added link {} with hash {} to db",
-        shortcut.link,
-        shortcut.hash
+/// Scans for the shortcuts not in the trash storing every one of the terms, along with
+/// shortcuts stored before search terms were
+async fn scan_terms(
+    client: &Client,
+    table_name: &str,
+    terms: &[String],
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    let names = (0..terms.len()).map(|i| format!(":term{i}")).collect::<Vec<_>>();
+    let contains = names
+        .iter()
+        .map(|name| format!("contains(search_terms, {name})"))
+        .collect::<Vec<_>>();
+    let filter = format!(
+        "{SHORTCUTS_FILTER} AND attribute_not_exists(deleted_at) \
+         AND (attribute_not_exists(search_terms) OR ({}))",
+        contains.join(" AND ")
     );
+    let values = names
+        .into_iter()
+        .zip(terms)
+        .map(|(name, term)| (name, AttributeValue::S(term.to_string())));
 
-    Ok(())
+    scan_with(client, table_name, &filter, values.collect()).await
 }
 
-/// Get the given hash's link from db
-pub async fn get_shortcut(
+/// Get the shortcuts stored before search terms were, which are missing from the search index
+pub async fn get_unindexed_shortcuts(
+    client: &Client,
+    table_name: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    metrics::track_db("get_unindexed_shortcuts", async {
+        let filter = format!("{SHORTCUTS_FILTER} AND attribute_exists(link) AND attribute_not_exists(search_terms)");
+        scan(client, table_name, &filter).await
+    })
+    .await
+}
+
+/// Scans every page of the table for items matching the filter expression, which is
+/// given the audit and search index prefixes as :audit and :search
+async fn scan(
+    client: &Client,
+    table_name: &str,
+    filter: &str,
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    scan_with(client, table_name, filter, HashMap::new()).await
+}
+
+/// Scans like [`scan`], giving the filter expression more values
+async fn scan_with(
+    client: &Client,
+    table_name: &str,
+    filter: &str,
+    mut values: HashMap<String, AttributeValue>,
+) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
+    let mut items = Vec::new();
+    let mut start_key = None;
+    // DynamoDB refuses values the expression doesn't use
+    for (name, prefix) in [(":audit", AUDIT_PREFIX), (":search", SEARCH_PREFIX)] {
+        if filter.contains(name) {
+            values.insert(name.to_string(), AttributeValue::S(prefix.to_string()));
+        }
+    }
+
+    loop {
+        let request = client
+            .scan()
+            .table_name(table_name)
+            .filter_expression(filter)
+            .set_expression_attribute_values(Some(values.clone()))
+            .set_exclusive_start_key(start_key);
+
+        tracing::debug!("Executing request [{request:?}] to scan db");
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+        match response.items {
+            None => {
+                return Err(DbError::RetrievalError(
+                    "Scan response did not function properly".to_string(),
+                ))
+            }
+            Some(page) => items.extend(page),
+        }
+
+        start_key = response.last_evaluated_key;
+        if start_key.is_none() {
+            return Ok(items);
+        }
+    }
+}
+
+/// Checks the table is reachable
+pub async fn describe_table(client: &Client, table_name: &str) -> Result<(), DbError> {
+    metrics::track_db("describe_table", async {
+        let request = client.describe_table().table_name(table_name);
+
+        tracing::debug!("Executing request [{request:?}] to describe table");
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+        match response.table() {
+            None => Err(DbError::RetrievalError(
+                "Describe response did not have a table".to_string(),
+            )),
+            Some(_) => Ok(()),
+        }
+    })
+    .await
+}
+
+/// Counts the shortcuts which aren't in the trash, leaving out audit items and the hashes
+/// kept from purged shortcuts. Takes a scan of the whole table
+pub async fn count_shortcuts(client: &Client, table_name: &str) -> Result<i64, DbError> {
+    metrics::track_db("count_shortcuts", async {
+        let mut count = 0;
+        let mut start_key = None;
+
+        loop {
+            let request = client
+                .scan()
+                .table_name(table_name)
+                .select(Select::Count)
+                .filter_expression("attribute_exists(link) AND attribute_not_exists(deleted_at)")
+                .set_exclusive_start_key(start_key);
+
+            tracing::debug!("Executing request [{request:?}] to count shortcuts in db");
+
+            let response = request
+                .send()
+                .await
+                .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+            count += response.count as i64;
+            start_key = response.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(count);
+            }
+        }
+    })
+    .await
+}
+
+/// Moves the given hash's shortcut to the trash at `deleted_at` unless it already is, or
+/// restores it with None. Only these attributes are written, so concurrent edits are kept
+pub async fn set_deleted(
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+    deleted_at: Option<u64>,
+    updated_at: u64,
+) -> Result<(), DbError> {
+    metrics::track_db("set_deleted", update_deleted(client, table_name, hash, deleted_at, updated_at)).await
+}
+
+/// Moves the shortcuts of the given hashes to the trash, several at a time, returning the
+/// hashes which were moved. Hashes missing or already in the trash are skipped
+pub async fn delete_shortcuts(
+    client: &Client,
+    table_name: &str,
+    hashes: &[String],
+    deleted_at: u64,
+) -> Result<Vec<String>, DbError> {
+    metrics::track_db("delete_shortcuts", async {
+        let mut deleted = Vec::with_capacity(hashes.len());
+
+        for chunk in hashes.chunks(MAX_CONCURRENT_UPDATES) {
+            let mut pending = JoinSet::new();
+            for hash in chunk {
+                let (client, table_name, hash) = (client.clone(), table_name.to_string(), hash.to_string());
+                pending.spawn(async move {
+                    let result = update_deleted(&client, &table_name, &hash, Some(deleted_at), deleted_at).await;
+                    (hash, result)
+                });
+            }
+            while let Some(result) = pending.join_next().await {
+                match result.map_err(|e| DbError::RequestError(e.to_string()))? {
+                    (hash, Ok(())) => deleted.push(hash),
+                    // Deleted by someone else since it was listed
+                    (_, Err(DbError::NotFoundError(_))) => {}
+                    (_, Err(e)) => return Err(e),
+                }
+            }
+        }
+
+        tracing::debug!("Set {} of {} links as deleted at {deleted_at}", deleted.len(), hashes.len());
+        Ok(deleted)
+    })
+    .await
+}
+
+async fn update_deleted(
     client: &Client,
     table_name: &str,
     hash: &str,
-) -> Result<String, DbError> {
+    deleted_at: Option<u64>,
+    updated_at: u64,
+) -> Result<(), DbError> {
     let request = client
-        .query()
+        .update_item()
         .table_name(table_name)
-        .key_condition_expression("link_hash = :hash")
-        .expression_attribute_values(":hash", AttributeValue::S(hash.to_string()))
-        .projection_expression("link");
+        .key("link_hash", AttributeValue::S(hash.to_string()))
+        .expression_attribute_values(":updated_at", AttributeValue::N(updated_at.to_string()));
+    let request = match deleted_at {
+        Some(deleted_at) => request
+            .condition_expression("attribute_exists(link_hash) AND attribute_not_exists(deleted_at)")
+            .update_expression("SET deleted_at = :deleted_at, updated_at = :updated_at")
+            .expression_attribute_values(":deleted_at", AttributeValue::N(deleted_at.to_string())),
+        None => request
+            .condition_expression("attribute_exists(link_hash)")
+            .update_expression("SET updated_at = :updated_at REMOVE deleted_at"),
+    };
 
-    tracing::debug!("Executing request [{request:?}] to get shortcut from db using hash");
+    tracing::debug!("Executing request [{request:?}] to set when shortcut was deleted in db");
 
-    let response = request
-        .send()
-        .await
-        .map_err(|e| DbError::RequestError(e.to_string()))?;
+    request.send().await.map_err(|e| {
+        match e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) {
+            true => DbError::NotFoundError(format!("No shortcut with {hash} to change")),
+            false => DbError::RequestError(e.to_string()),
+        }
+    })?;
 
-    match response.items {
-        None => Err(DbError::RetrievalError(
-            "Query response did not have any items to check".to_string(),
-        )),
-        Some(items) => match items.len() {
-            1 => {
-                let link = items[0].get("link");
-                if link.is_none() || link.unwrap().as_s().is_err() {
-                    Err(DbError::RetrievalError(
-                        "Query response item did not provide a valid link".to_string(),
-                    ))
-                } else {
-                    let link_str = link.unwrap().as_s().unwrap().to_string();
-                    tracing::debug!("Fetched link {link_str} from hash {hash}");
-                    Ok(link_str)
-                }
+    tracing::debug!("Set link with {hash} as deleted at {deleted_at:?}");
+    Ok(())
+}
+
+/// Replaces the title, description, tags, search terms and update time of the given hash's shortcut, removing the
+/// ones which are empty. Deleted shortcuts can't be changed
+pub async fn update_metadata(client: &Client, table_name: &str, shortcut: &Shortcut) -> Result<(), DbError> {
+    metrics::track_db("update_metadata", async {
+        let mut set = Vec::new();
+        let mut remove = Vec::new();
+        let mut request = client
+            .update_item()
+            .table_name(table_name)
+            .key("link_hash", AttributeValue::S(shortcut.hash.to_string()))
+            .condition_expression("attribute_exists(link_hash) AND attribute_not_exists(deleted_at)");
+
+        match &shortcut.title {
+            Some(title) => {
+                set.push("title = :title");
+                request = request.expression_attribute_values(":title", AttributeValue::S(title.to_string()));
             }
-            l => Err(DbError::RetrievalError(format!(
-                "Query had {l} entries instead of one"
-            ))),
-        },
-    }
+            None => remove.push("title"),
+        }
+        match &shortcut.description {
+            Some(description) => {
+                set.push("description = :description");
+                request = request
+                    .expression_attribute_values(":description", AttributeValue::S(description.to_string()));
+            }
+            None => remove.push("description"),
+        }
+        match shortcut.tags.is_empty() {
+            false => {
+                set.push("tags = :tags");
+                request = request.expression_attribute_values(":tags", AttributeValue::Ss(shortcut.tags.clone()));
+            }
+            true => remove.push("tags"),
+        }
+        let search_terms = search::terms(shortcut);
+        match search_terms.is_empty() {
+            false => {
+                set.push("search_terms = :search_terms");
+                request = request.expression_attribute_values(":search_terms", AttributeValue::Ss(search_terms));
+            }
+            true => remove.push("search_terms"),
+        }
+        if let Some(updated_at) = shortcut.updated_at {
+            set.push("updated_at = :updated_at");
+            request = request.expression_attribute_values(":updated_at", AttributeValue::N(updated_at.to_string()));
+        }
+
+        let mut expression = Vec::new();
+        if !set.is_empty() {
+            expression.push(format!("SET {}", set.join(", ")));
+        }
+        if !remove.is_empty() {
+            expression.push(format!("REMOVE {}", remove.join(", ")));
+        }
+        let request = request.update_expression(expression.join(" "));
+
+        tracing::debug!("Executing request [{request:?}] to update shortcut metadata in db");
+
+        request.send().await.map_err(|e| {
+            match e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) {
+                true => DbError::NotFoundError(format!("No shortcut with {} to update", shortcut.hash)),
+                false => DbError::RequestError(e.to_string()),
+            }
+        })?;
+
+        tracing::debug!("Updated metadata of link with {}", shortcut.hash);
+        Ok(())
+    })
+    .await
 }
 
-pub async fn get_all_shortcuts(
+/// How often items DynamoDB leaves unprocessed in a batch are asked for again
+const MAX_BATCH_RETRIES: usize = 5;
+
+/// Drops everything but the hash and deletion time of a deleted shortcut, so it can no
+/// longer be restored while its hash stays taken
+pub async fn purge_shortcut(client: &Client, table_name: &str, hash: &str) -> Result<(), DbError> {
+    metrics::track_db("purge_shortcut", async {
+        let request = client
+            .update_item()
+            .table_name(table_name)
+            .key("link_hash", AttributeValue::S(hash.to_string()))
+            .condition_expression("attribute_exists(deleted_at)")
+            .update_expression("REMOVE link, prefix, password_hash, title, description, tags, search_terms");
+
+        tracing::debug!("Executing request [{request:?}] to purge shortcut from db");
+
+        request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+        tracing::debug!("Purged link with {hash}");
+        Ok(())
+    })
+    .await
+}
+
+/// Appends the event to the audit log of its hash, numbering it with the hash's counter.
+/// Events are stored in items of their own which are never changed or removed, and
+/// outlive the shortcut itself
+pub async fn append_audit(client: &Client, table_name: &str, event: &AuditEvent) -> Result<(), DbError> {
+    metrics::track_db("append_audit", async {
+        let request = client
+            .update_item()
+            .table_name(table_name)
+            .key("link_hash", AttributeValue::S(format!("{AUDIT_PREFIX}{}", event.hash)))
+            .update_expression("ADD event_count :one")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .return_values(ReturnValue::UpdatedNew);
+
+        tracing::debug!("Executing request [{request:?}] to number audit event in db");
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+        let seq = response
+            .attributes
+            .as_ref()
+            .and_then(|a| a.get("event_count"))
+            .and_then(|c| c.as_n().ok())
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| DbError::RetrievalError("Audit counter was not returned".to_string()))?;
+
+        let request = client
+            .put_item()
+            .table_name(table_name)
+            .condition_expression("attribute_not_exists(link_hash)")
+            .set_item(Some(event.to_item(seq)));
+
+        tracing::debug!("Executing request [{request:?}] to append to audit log in db");
+
+        request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+        tracing::debug!("Logged {} of link with {} as event {seq}", event.action.as_str(), event.hash);
+        Ok(())
+    })
+    .await
+}
+
+/// Keys DynamoDB accepts in one batch read
+const MAX_BATCH_GET: usize = 100;
+
+/// Get the audit log of the given hash, oldest first
+pub async fn get_audit(client: &Client, table_name: &str, hash: &str) -> Result<Vec<AuditEvent>, DbError> {
+    metrics::track_db("get_audit", async {
+        let request = client
+            .get_item()
+            .table_name(table_name)
+            .key("link_hash", AttributeValue::S(format!("{AUDIT_PREFIX}{hash}")));
+
+        tracing::debug!("Executing request [{request:?}] to get audit counter from db");
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+        let count: u64 = response
+            .item
+            .as_ref()
+            .and_then(|item| item.get("event_count"))
+            .and_then(|c| c.as_n().ok())
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(0);
+
+        let keys = (1..=count)
+            .map(|seq| HashMap::from([("link_hash".to_string(), audit_key(hash, seq))]))
+            .collect::<Vec<_>>();
+        let items = batch_get(client, table_name, &keys).await?;
+        let mut events = items.iter().filter_map(AuditEvent::from_item).collect::<Vec<_>>();
+        events.sort_by_key(|(seq, _)| *seq);
+        Ok(events.into_iter().map(|(_, event)| event).collect())
+    })
+    .await
+}
+
+/// Get the items with the given keys in batches, in no particular order and leaving out
+/// the ones which don't exist
+async fn batch_get(
     client: &Client,
     table_name: &str,
+    keys: &[HashMap<String, AttributeValue>],
 ) -> Result<Vec<HashMap<String, AttributeValue>>, DbError> {
-    let request = client.scan().table_name(table_name);
+    let mut items = Vec::with_capacity(keys.len());
 
-    tracing::debug!("Executing request [{request:?}] to get shortcut from db using hash");
+    for chunk in keys.chunks(MAX_BATCH_GET) {
+        let mut keys = Some(
+            KeysAndAttributes::builder()
+                .set_keys(Some(chunk.to_vec()))
+                .build()
+                .map_err(|e| DbError::RequestError(e.to_string()))?,
+        );
 
-    let response = request
-        .send()
-        .await
-        .map_err(|e| DbError::RequestError(e.to_string()))?;
+        // DynamoDB may leave some keys unprocessed when throttling, which are asked for again
+        for _ in 0..MAX_BATCH_RETRIES {
+            let Some(pending) = keys.take() else { break };
+            let request = client.batch_get_item().request_items(table_name, pending);
+
+            tracing::debug!("Executing request [{request:?}] to get items from db");
 
-    match response.items {
-        None => Err(DbError::RetrievalError(
-            "Scan response did not function properly".to_string(),
-        )),
-        Some(items) => Ok(items),
+            let response = request
+                .send()
+                .await
+                .map_err(|e| DbError::RequestError(e.to_string()))?;
+
+            items.extend(
+                response
+                    .responses
+                    .and_then(|mut responses| responses.remove(table_name))
+                    .unwrap_or_default(),
+            );
+            keys = response
+                .unprocessed_keys
+                .and_then(|mut unprocessed| unprocessed.remove(table_name));
+        }
+
+        if keys.is_some() {
+            return Err(DbError::RequestError("Items were left unprocessed".to_string()));
+        }
     }
+
+    Ok(items)
 }
 
-/// Deletes the given hash's shortcut from db
-pub async fn delete_shortcut(client: &Client, table_name: &str, hash: &str) -> Result<(), DbError> {
+/// Adds the hash to the search index under every added term and takes it out from under
+/// every removed one
+pub async fn index_terms(
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+    added: &[String],
+    removed: &[String],
+) -> Result<(), DbError> {
+    metrics::track_db("index_terms", async {
+        let updates = added
+            .iter()
+            .map(|term| (term, true))
+            .chain(removed.iter().map(|term| (term, false)))
+            .collect::<Vec<_>>();
+
+        for chunk in updates.chunks(MAX_CONCURRENT_UPDATES) {
+            let mut pending = JoinSet::new();
+            for &(term, add) in chunk {
+                let (client, table_name) = (client.clone(), table_name.to_string());
+                let (term, hash) = (term.to_string(), hash.to_string());
+                pending.spawn(async move {
+                    match add {
+                        true => add_term(&client, &table_name, &term, &hash).await,
+                        false => remove_term(&client, &table_name, &term, &hash).await,
+                    }
+                });
+            }
+            while let Some(result) = pending.join_next().await {
+                result.map_err(|e| DbError::RequestError(e.to_string()))??;
+            }
+        }
+
+        tracing::debug!("Indexed {} terms of link with {hash}", updates.len());
+        Ok(())
+    })
+    .await
+}
+
+/// Lists the hash under the term, or marks the term too common once it lists the most
+/// hashes it can. A term stays too common from then on, as hashes added later are missing
+async fn add_term(client: &Client, table_name: &str, term: &str, hash: &str) -> Result<(), DbError> {
     let request = client
-        .delete_item()
+        .update_item()
+        .table_name(table_name)
+        .set_key(Some(search_key(term)))
+        .condition_expression("attribute_not_exists(too_common) AND (attribute_not_exists(hashes) OR size(hashes) < :max)")
+        .update_expression("ADD hashes :hash")
+        .expression_attribute_values(":hash", AttributeValue::Ss(vec![hash.to_string()]))
+        .expression_attribute_values(":max", AttributeValue::N(MAX_TERM_HASHES.to_string()));
+
+    tracing::debug!("Executing request [{request:?}] to add shortcut to search index in db");
+
+    match request.send().await {
+        Ok(_) => Ok(()),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+            let request = client
+                .update_item()
+                .table_name(table_name)
+                .set_key(Some(search_key(term)))
+                .update_expression("SET too_common = :true")
+                .expression_attribute_values(":true", AttributeValue::Bool(true));
+
+            tracing::debug!("Executing request [{request:?}] to mark search term as too common in db");
+
+            request
+                .send()
+                .await
+                .map_err(|e| DbError::RequestError(e.to_string()))?;
+            Ok(())
+        }
+        Err(e) => Err(DbError::RequestError(e.to_string())),
+    }
+}
+
+/// Takes the hash out from under the term
+async fn remove_term(client: &Client, table_name: &str, term: &str, hash: &str) -> Result<(), DbError> {
+    let request = client
+        .update_item()
         .table_name(table_name)
-        .key("link_hash", AttributeValue::S(hash.to_string()));
+        .set_key(Some(search_key(term)))
+        .update_expression("DELETE hashes :hash")
+        .expression_attribute_values(":hash", AttributeValue::Ss(vec![hash.to_string()]));
 
-    tracing::debug!("Executing request [{request:?}] to get shortcut from db using hash");
+    tracing::debug!("Executing request [{request:?}] to remove shortcut from search index in db");
 
-    let _response = request
+    request
         .send()
         .await
         .map_err(|e| DbError::RequestError(e.to_string()))?;
-
-    tracing::debug!("Deleted link with {hash}");
     Ok(())
 }
 
+/// Stores the search terms of a shortcut stored before they were, once it has been indexed
+pub async fn set_search_terms(client: &Client, table_name: &str, shortcut: &Shortcut) -> Result<(), DbError> {
+    metrics::track_db("set_search_terms", async {
+        let request = client
+            .update_item()
+            .table_name(table_name)
+            .key("link_hash", AttributeValue::S(shortcut.hash.to_string()))
+            .condition_expression("attribute_exists(link)")
+            .update_expression("SET search_terms = :search_terms")
+            .expression_attribute_values(":search_terms", AttributeValue::Ss(search::terms(shortcut)));
+
+        tracing::debug!("Executing request [{request:?}] to set search terms of shortcut in db");
+
+        request
+            .send()
+            .await
+            .map_err(|e| DbError::RequestError(e.to_string()))?;
+        Ok(())
+    })
+    .await
+}
+
+/// Get the audit logs of every hash, each oldest first
+pub async fn get_all_audits(client: &Client, table_name: &str) -> Result<Vec<AuditEvent>, DbError> {
+    metrics::track_db("get_all_audits", async {
+        let items = scan(client, table_name, "begins_with(link_hash, :audit)").await?;
+        let mut events = items.iter().filter_map(AuditEvent::from_item).collect::<Vec<_>>();
+        events.sort_by(|(a_seq, a), (b_seq, b)| a.hash.cmp(&b.hash).then(a_seq.cmp(b_seq)));
+        Ok(events.into_iter().map(|(_, event)| event).collect())
+    })
+    .await
+}
+
+/// Removes the given hash's item entirely, freeing the hash. Only used to clean up tests
+#[cfg(test)]
+pub async fn delete_shortcut(client: &Client, table_name: &str, hash: &str) -> Result<(), DbError> {
+    client
+        .delete_item()
+        .table_name(table_name)
+        .key("link_hash", AttributeValue::S(hash.to_string()))
+        .send()
+        .await
+        .map_err(|e| DbError::RequestError(e.to_string()))?;
+    Ok(())
+}
 
 #[cfg(test)]
 #[tokio::test]
+#[allow(clippy::result_large_err)]
 async fn test_init_db() -> Result<(), aws_sdk_dynamodb::Error> {
     dotenv::dotenv().ok();
     let client = init_db_client().await;
@@ -176,14 +1093,15 @@ async fn test_add_to_db() -> Result<(), DbError> {
     let shortcut = Shortcut {
         link: "https://www.google.com".to_string(),
         hash: "hello-world".to_string(),
+        ..Shortcut::default()
     };
     add_shortcut(&client, &table_name, &shortcut).await?;
 
-    let link = get_shortcut(&client, &table_name, &shortcut.hash)
+    let fetched = get_shortcut(&client, &table_name, &shortcut.hash)
         .await
         .unwrap();
 
-    assert_eq!(link, shortcut.link);
+    assert_eq!(fetched.link, shortcut.link);
 
     delete_shortcut(&client, &table_name, &shortcut.hash).await?;
 
@@ -192,3 +1110,86 @@ async fn test_add_to_db() -> Result<(), DbError> {
         Err(_) => Ok(()),
     }
 }
+
+#[test]
+fn test_shortcut_items() {
+    let shortcut = Shortcut {
+        link: "https://www.google.com".to_string(),
+        hash: "hello-world".to_string(),
+        prefix: true,
+        created_at: Some(1_000),
+        updated_at: Some(2_000),
+        created_by: Some("alice@laptop".to_string()),
+        deleted_at: Some(2_000),
+        title: Some("Search".to_string()),
+        tags: vec!["infra".to_string(), "web".to_string()],
+        ..Shortcut::default()
+    };
+
+    let item = shortcut.to_item();
+    let parsed = Shortcut::from_item(&item).unwrap();
+    assert_eq!(parsed.link, shortcut.link);
+    assert!(parsed.prefix);
+    assert_eq!(parsed.title.as_deref(), Some("Search"));
+    assert_eq!(parsed.description, None);
+    assert!(parsed.has_tags(&["web".to_string()]));
+    assert!(!parsed.has_tags(&["web".to_string(), "docs".to_string()]));
+    assert_eq!(parsed.created_at, Some(1_000));
+    assert_eq!(parsed.updated_at, Some(2_000));
+    assert_eq!(parsed.created_by.as_deref(), Some("alice@laptop"));
+    assert!(parsed.is_deleted() && !parsed.is_purged());
+    let search_terms = item.get("search_terms").and_then(|t| t.as_ss().ok()).unwrap();
+    assert!(search_terms.contains(&"search".to_string()));
+
+    // Purged items only keep their hash and deletion time
+    let mut purged = item.clone();
+    purged.remove("link");
+    assert!(Shortcut::from_item(&purged).unwrap().is_purged());
+
+    purged.remove("deleted_at");
+    assert!(Shortcut::from_item(&purged).is_none());
+}
+
+#[test]
+fn test_audit_items() {
+    let event = AuditEvent {
+        hash: "hello-world".to_string(),
+        at: 1_000,
+        action: AuditAction::Create,
+        actor: "ip:10.0.0.7 claiming bob@desk".to_string(),
+        detail: Some("https://www.google.com".to_string()),
+    };
+
+    let item = event.to_item(3);
+    assert_eq!(item.get("link_hash"), Some(&AttributeValue::S("audit#hello-world#3".to_string())));
+
+    let (seq, parsed) = AuditEvent::from_item(&item).unwrap();
+    assert_eq!(seq, 3);
+    assert_eq!(parsed.hash, "hello-world");
+    assert_eq!(parsed.at, 1_000);
+    assert_eq!(parsed.action, AuditAction::Create);
+    assert_eq!(parsed.actor, "ip:10.0.0.7 claiming bob@desk");
+    assert_eq!(parsed.detail.as_deref(), Some("https://www.google.com"));
+
+    // Audit items are never shortcuts, and neither shortcuts nor counters are events
+    assert!(Shortcut::from_item(&item).is_none());
+    let counter = HashMap::from([
+        ("link_hash".to_string(), AttributeValue::S("audit#hello-world".to_string())),
+        ("event_count".to_string(), AttributeValue::N("3".to_string())),
+    ]);
+    assert!(AuditEvent::from_item(&counter).is_none());
+    assert!(Shortcut::from_item(&counter).is_none());
+    let shortcut = HashMap::from([
+        ("link".to_string(), AttributeValue::S("https://www.google.com".to_string())),
+        ("link_hash".to_string(), AttributeValue::S("hello-world".to_string())),
+    ]);
+    assert!(AuditEvent::from_item(&shortcut).is_none());
+}
+
+#[test]
+fn test_internal_keys() {
+    assert!(is_internal_key("audit#happy-cat#0"));
+    assert!(is_internal_key("search#kube"));
+    assert!(!is_internal_key("happy-cat"));
+    assert!(!is_internal_key("audit"));
+}
@@ -0,0 +1,251 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use socket2::{Domain, Socket, Type};
+use thiserror::Error;
+
+use crate::args::ClapArgs;
+
+#[derive(Error, Debug)]
+pub enum ListenError {
+    #[error("expected an address like 127.0.0.1:8080, [::1]:8080 or unix:/path/to.sock")]
+    ParseError(String),
+
+    #[error("could not bind to {0}: {1}")]
+    BindError(String, #[source] io::Error),
+}
+
+/// Address the server listens on, either a TCP socket address or a Unix domain socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = ListenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err(ListenError::ParseError(s.to_string())),
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None => s
+                .parse::<SocketAddr>()
+                .map(ListenAddr::Tcp)
+                .map_err(|_| ListenError::ParseError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound socket, ready to be handed to the server
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    /// The address actually bound, with the real port if port 0 was asked for
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| ListenAddr::Unix(path.to_path_buf()))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unnamed socket")),
+        }
+    }
+}
+
+/// Addresses the server should listen on: every --listen, or else --host and --port
+pub fn listen_addrs(args: &ClapArgs) -> Vec<ListenAddr> {
+    match args.listen.is_empty() {
+        false => args.listen.clone(),
+        true => vec![ListenAddr::Tcp(SocketAddr::from((
+            args.host
+                .parse::<IpAddr>()
+                .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            args.port,
+        )))],
+    }
+}
+
+/// Binds every address once. A TCP port already in use is swapped for a random free
+/// one unless `strict_port` is set.
+pub fn bind_all(addrs: &[ListenAddr], strict_port: bool) -> Result<Vec<Listener>, ListenError> {
+    addrs
+        .iter()
+        .map(|addr| match bind(addr) {
+            Err(e) if !strict_port && e.kind() == io::ErrorKind::AddrInUse => match addr {
+                ListenAddr::Tcp(tcp_addr) => {
+                    tracing::warn!("Address {addr} is in use, listening on a random port instead");
+                    bind(&ListenAddr::Tcp(SocketAddr::new(tcp_addr.ip(), 0)))
+                }
+                ListenAddr::Unix(_) => Err(e),
+            },
+            result => result,
+        }
+        .map_err(|e| ListenError::BindError(addr.to_string(), e)))
+        .collect()
+}
+
+fn bind(addr: &ListenAddr) -> io::Result<Listener> {
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+            // Keep IPv6 sockets to IPv6 so [::] and 0.0.0.0 can be listened on side by side
+            if addr.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            #[cfg(unix)]
+            socket.set_reuse_address(true)?;
+            socket.bind(&(*addr).into())?;
+            socket.listen(1024)?;
+            socket.set_nonblocking(true)?;
+            Ok(Listener::Tcp(socket.into()))
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+
+            // A socket file nobody answers on is left over from a server that did not stop
+            // cleanly, but anything else at the path is never removed
+            if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                if !metadata.file_type().is_socket() {
+                    return Err(io::Error::new(io::ErrorKind::AddrInUse, "path exists and is not a socket"));
+                }
+                if std::os::unix::net::UnixStream::connect(path).is_err() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            let listener = std::os::unix::net::UnixListener::bind(path)?;
+            listener.set_nonblocking(true)?;
+            Ok(Listener::Unix(listener))
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix sockets are only supported on unix",
+        )),
+    }
+}
+
+/// Binds the Unix socket links are managed through, with `mode` deciding who may connect
+pub fn bind_admin_socket(path: &Path, mode: u32) -> Result<Listener, ListenError> {
+    let addr = ListenAddr::Unix(path.to_path_buf());
+    let listener = bind(&addr).map_err(|e| ListenError::BindError(addr.to_string(), e))?;
+
+    // Set on the file rather than through the umask, which would change it for every
+    // thread of the process. Until then the socket has the usual umask's permissions,
+    // which don't let other users connect either
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .map_err(|e| ListenError::BindError(addr.to_string(), e))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    Ok(listener)
+}
+
+/// Client of a Unix domain socket, known by the user id the kernel gives for it
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug)]
+pub struct UnixPeer {
+    pub uid: Option<u32>,
+}
+
+#[cfg(unix)]
+impl axum::extract::connect_info::Connected<&tokio::net::UnixStream> for UnixPeer {
+    fn connect_info(stream: &tokio::net::UnixStream) -> UnixPeer {
+        UnixPeer {
+            uid: stream.peer_cred().ok().map(|cred| cred.uid()),
+        }
+    }
+}
+
+/// Accepts connections on a Unix domain socket for the server
+#[cfg(unix)]
+pub struct UnixAccept(pub tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = tokio::net::UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|result| Some(result.map(|(stream, _)| stream)))
+    }
+}
+
+#[test]
+fn test_listen_addrs() {
+    assert_eq!(
+        "127.0.0.1:8080".parse::<ListenAddr>().unwrap(),
+        ListenAddr::Tcp(SocketAddr::from(([127, 0, 0, 1], 8080)))
+    );
+    assert_eq!(
+        "[::1]:8080".parse::<ListenAddr>().unwrap().to_string(),
+        "[::1]:8080"
+    );
+    assert_eq!(
+        "unix:/tmp/links.sock".parse::<ListenAddr>().unwrap(),
+        ListenAddr::Unix(PathBuf::from("/tmp/links.sock"))
+    );
+    assert!("localhost".parse::<ListenAddr>().is_err());
+    assert!("unix:".parse::<ListenAddr>().is_err());
+
+    let taken = bind_all(&["127.0.0.1:0".parse().unwrap()], true).unwrap();
+    let taken_addr = taken[0].local_addr().unwrap();
+
+    assert!(matches!(
+        bind_all(std::slice::from_ref(&taken_addr), true),
+        Err(ListenError::BindError(_, _))
+    ));
+
+    let fallback = bind_all(std::slice::from_ref(&taken_addr), false).unwrap();
+    assert_ne!(fallback[0].local_addr().unwrap(), taken_addr);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let socket = std::env::temp_dir().join(format!("cli_shortener_admin_{}.sock", std::process::id()));
+        let _admin = bind_admin_socket(&socket, 0o600).unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(&socket).unwrap();
+
+        // Files that aren't sockets are left alone
+        let file = std::env::temp_dir().join(format!("cli_shortener_file_{}.sock", std::process::id()));
+        std::fs::write(&file, "keep").unwrap();
+        assert!(matches!(
+            bind_all(&[ListenAddr::Unix(file.clone())], false),
+            Err(ListenError::BindError(_, e)) if e.kind() == io::ErrorKind::AddrInUse
+        ));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep");
+        std::fs::remove_file(&file).unwrap();
+    }
+}
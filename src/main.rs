@@ -1,137 +1,867 @@
 use std::{
     env,
     fs::File,
-    io::{Read, Write},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    future::Future,
+    io::{self, IsTerminal, Read, Write},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use access_log::AccessLog;
 use args::ClapArgs;
-use axum::routing;
+use axum::{extract::ConnectInfo, middleware, routing, Extension};
+use cache::ShortcutCache;
 use clap::Parser;
-use cli_table::{print_stdout, Cell, CellStruct, Style, Table};
+use client::AdminClient;
+use daemon::DaemonError;
 use dotenv::dotenv;
-use reqwest::StatusCode;
+use listener::{ListenAddr, Listener};
+use output::{CliError, Output};
+use rate_limit::RateLimiter;
+use shortcuts::ShortcutError;
+use reqwest::{Method, StatusCode};
+use tokio::{signal, sync::watch, task::JoinSet};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
+mod access_log;
 mod args;
+mod audit;
+mod batch;
+mod cache;
+mod client;
+mod clipboard;
 mod controller;
+mod daemon;
 mod db;
+mod listener;
+mod metrics;
+mod output;
+mod prompt;
+mod qr;
+mod rate_limit;
+mod search;
+mod service;
+mod shortcuts;
+mod telemetry;
 mod utils;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     let args = args::ClapArgs::parse();
+    let format = args.output;
+
+    if let args::EntityType::Start(args::StartCommand { detach: false }) = args.entity_type {
+        return init(args).await;
+    }
+
+    match run(args).await {
+        Ok(output) => {
+            output.print(format);
+            ExitCode::from(output.exit_code())
+        }
+        Err(e) => {
+            e.print(format);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let (local_addr, path) = match matches!(args.entity_type, args::EntityType::Start) {
+/// Runs every command except starting the server in the foreground
+async fn run(args: ClapArgs) -> Result<Output, CliError> {
+    // Links can be managed straight from storage without a running server
+    let direct = args.direct
+        && matches!(
+            args.entity_type,
+            args::EntityType::New(_)
+                | args::EntityType::Delete(_)
+                | args::EntityType::Clear(_)
+                | args::EntityType::List(_)
+                | args::EntityType::Edit(_)
+                | args::EntityType::Search(_)
+                | args::EntityType::Trash
+                | args::EntityType::Restore(_)
+                | args::EntityType::Audit(_)
+        );
+
+    let (local_addr, admin_socket, path) = match matches!(
+        args.entity_type,
+        args::EntityType::Start(_)
+            | args::EntityType::Stop
+            | args::EntityType::Restart
+            | args::EntityType::InstallService(_)
+    ) {
         false => match get_local_addr() {
-            Ok(addr) => addr,
-            Err(_) => {
-                println!("Could not get address to access cli_shortener");
-                return;
+            Ok((addr, admin_socket, path)) => (addr.unwrap_or_else(|| default_addr(&args)), admin_socket, path),
+            // Without a server, links point at where it would listen
+            Err(_) if direct || matches!(args.entity_type, args::EntityType::Qr(_)) => {
+                (default_addr(&args), None, args.path.clone())
             }
+            Err(_) => return Err(CliError::NotRunning()),
         },
-        true => (SocketAddr::from(([127, 0, 0, 1], 0)), "".to_string()), // just to initialize the variable
+        true => (SocketAddr::from(([127, 0, 0, 1], 0)), None, "".to_string()), // just to initialize the variable
     };
+    let client = AdminClient::new(local_addr, args.admin_socket.clone().or(admin_socket), &path);
 
     match args.entity_type {
-        args::EntityType::Start => {
-            init(args).await;
-        }
-        args::EntityType::List => {
-            if let Ok(resp) = reqwest::get(format!("http://{local_addr}/{path}/all")).await {
-                if resp.status() == StatusCode::OK {
-                    if let Ok(shortcuts) = resp.json::<Vec<db::Shortcut>>().await {
-                        if !shortcuts.is_empty() {
-                            let shortcuts_iter = shortcuts.into_iter();
-
-                            let table = shortcuts_iter
-                                .map(|s| vec![s.link.cell(), s.hash.cell()])
-                                .collect::<Vec<Vec<CellStruct>>>()
-                                .table()
-                                .title(vec![
-                                    "Original Link".cell().bold(true),
-                                    "Shortcut Link".cell().bold(true),
-                                ])
-                                .bold(true);
-
-                            if print_stdout(table).is_err() {
-                                println!("\nCould not show all shortcut links")
-                            }
-                        } else {
-                            println!("\nNo shortcuts have been created yet. Use the new command to create a new link")
+        args::EntityType::Start(_) => start_detached(&service::server_args(&args)),
+        args::EntityType::Stop => {
+            match daemon::stop(Duration::from_secs(args.drain_timeout + 5)) {
+                Ok(pid) => Ok(Output::record(
+                    format!("Stopped the links server (PID {pid})"),
+                    vec![("pid", pid.into())],
+                )),
+                Err(DaemonError::NotRunning()) => Err(CliError::NotRunning()),
+                Err(e) => Err(CliError::Failed(format!("Could not stop the links server: {e}"))),
+            }
+        }
+        args::EntityType::Restart => {
+            match daemon::stop(Duration::from_secs(args.drain_timeout + 5)) {
+                // The server comes back with the options it was started with, not the ones given here
+                Ok(_) | Err(DaemonError::NotRunning()) => {
+                    start_detached(&daemon::saved_args().unwrap_or_else(|| service::server_args(&args)))
+                }
+                Err(e) => Err(CliError::Failed(format!("Could not stop the links server: {e}"))),
+            }
+        }
+        args::EntityType::InstallService(ref command) => {
+            if command.dry_run {
+                let exe = env::current_exe().unwrap_or_default();
+                let cwd = env::current_dir().unwrap_or_default();
+                let units = service::unit_files(&args, command, &exe, &cwd);
+                let message = units
+                    .iter()
+                    .map(|(name, contents)| format!("# {name}\n{contents}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                return Ok(Output::records(
+                    vec!["unit", "contents"],
+                    units.into_iter().map(|(name, contents)| vec![name.into(), contents.into()]).collect(),
+                )
+                .or_message(message));
+            }
+
+            match service::install(&args, command) {
+                Err(e) => Err(CliError::Failed(format!("Could not install the service units: {e}"))),
+                Ok(paths) => {
+                    let systemctl = match command.system {
+                        true => "systemctl",
+                        false => "systemctl --user",
+                    };
+                    let unit = match command.socket {
+                        true => "cli_shortener.socket",
+                        false => "cli_shortener.service",
+                    };
+                    let written = paths
+                        .iter()
+                        .map(|path| format!("Wrote {}", path.display()))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    Ok(Output::records(
+                        vec!["path"],
+                        paths.iter().map(|path| vec![path.display().to_string().into()]).collect(),
+                    )
+                    .or_message(format!(
+                        "{written}\n\nEnable it with: {systemctl} daemon-reload && {systemctl} enable --now {unit}"
+                    )))
+                }
+            }
+        }
+        args::EntityType::Status => {
+            // Asked through the client, so servers only listening on Unix sockets are reached too
+            let status = match client.send(Method::GET, "readyz", None).await {
+                Err(_) => return Err(CliError::NotRunning()),
+                Ok((_, body)) => match serde_json::from_str::<utils::ServerStatus>(&body) {
+                    Err(_) => return Err(CliError::Failed("Could not get the status of the links server".to_string())),
+                    Ok(status) => status,
+                },
+            };
+
+            let uptime = status.uptime_secs;
+            let pid = daemon::running_pid();
+            let mut message = vec![
+                format!("Server: http://{local_addr}/{path}"),
+                format!("Version: {}", status.version),
+                format!("Uptime: {}h {}m {}s", uptime / 3600, uptime / 60 % 60, uptime % 60),
+                format!(
+                    "Backend: {} ({})",
+                    status.backend,
+                    if status.ready { "reachable" } else { "unreachable" }
+                ),
+            ];
+            if let Some(count) = status.link_count {
+                message.push(format!("Links: {count}"));
+            }
+            if let Some(pid) = pid {
+                message.push(format!("Running in background: PID {pid}"));
+            }
+
+            Ok(Output::record(
+                message.join("\n"),
+                vec![
+                    ("server", format!("http://{local_addr}/{path}").into()),
+                    ("version", status.version.into()),
+                    ("uptime_secs", uptime.into()),
+                    ("backend", status.backend.into()),
+                    ("ready", status.ready.into()),
+                    ("link_count", status.link_count.into()),
+                    ("pid", pid.into()),
+                ],
+            )
+            // Scripts checking the server need it to be able to serve links, not only to be up
+            .failed_if(!status.ready))
+        }
+        args::EntityType::List(ref list_command) => {
+            let filter = utils::ListFilter {
+                tag: Some(list_command.tags.join(",")).filter(|tag| !tag.is_empty()),
+            };
+
+            let shortcuts = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    let tags = filter.tags();
+                    shortcuts::list_shortcuts(&db_client, &table_name)
+                        .await
+                        .ok()
+                        .map(|shortcuts| shortcuts.into_iter().filter(|s| s.has_tags(&tags)).collect())
+                }
+                false => match client.send(Method::GET, &filter.route(), None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => serde_json::from_str::<Vec<db::Shortcut>>(&body).ok(),
+                    Ok(_) => None,
+                },
+            };
+
+            let empty = match filter.tag {
+                None => "No shortcuts have been created yet. Use the new command to create a new link",
+                Some(_) => "No shortcuts have all of the given tags",
+            };
+            match shortcuts {
+                None => Err(CliError::Failed("No links could be found".to_string())),
+                Some(shortcuts) => Ok(Output::records(
+                    vec!["link", "hash", "title", "description", "tags"],
+                    shortcuts
+                        .into_iter()
+                        .map(|s| {
+                            vec![s.link.into(), s.hash.into(), s.title.into(), s.description.into(), s.tags.into()]
+                        })
+                        .collect(),
+                )
+                .or_message(empty)),
+            }
+        }
+        args::EntityType::Search(search_command) => {
+            let query = utils::SearchQuery {
+                q: search_command.query.join(" "),
+                limit: Some(search_command.limit.into()),
+            };
+            match search::query_terms(&query.q).len() {
+                0 => return Err(CliError::InvalidInput("Give some words to search for".to_string())),
+                n if n > search::MAX_QUERY_TERMS => {
+                    return Err(CliError::InvalidInput(format!(
+                        "Give at most {} words to search for",
+                        search::MAX_QUERY_TERMS
+                    )))
+                }
+                _ => {}
+            }
+
+            let shortcuts = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    let limit = search_command.limit.into();
+                    shortcuts::search_shortcuts(&db_client, &table_name, &query.q, limit).await.ok()
+                }
+                false => match client.send(Method::GET, &query.route(), None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => serde_json::from_str::<Vec<db::Shortcut>>(&body).ok(),
+                    Ok(_) => None,
+                },
+            };
+
+            match shortcuts {
+                None => Err(CliError::Failed("Links could not be searched".to_string())),
+                Some(shortcuts) => Ok(Output::records(
+                    vec!["link", "hash", "title", "tags"],
+                    shortcuts
+                        .into_iter()
+                        .map(|s| vec![s.link.into(), s.hash.into(), s.title.into(), s.tags.into()])
+                        .collect(),
+                )
+                .or_message(format!("No shortcuts match {:?}", query.q))),
+            }
+        }
+        args::EntityType::Edit(edit_command) => {
+            let hash = utils::shortcut_hash(&edit_command.shortcut);
+            let edit_link = utils::EditLink {
+                title: edit_command.title,
+                description: edit_command.description,
+                tags: match (edit_command.clear_tags, edit_command.tags.is_empty()) {
+                    (true, _) => Some(Vec::new()),
+                    (false, false) => Some(edit_command.tags),
+                    (false, true) => None,
+                },
+            };
+            if edit_link.title.is_none() && edit_link.description.is_none() && edit_link.tags.is_none() {
+                return Err(CliError::InvalidInput(
+                    "Give at least one of --title, --desc, --tag or --clear-tags to change".to_string(),
+                ));
+            }
+
+            let edited = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    match shortcuts::edit_shortcut(&db_client, &table_name, hash, &edit_link, &audit::local_user()).await {
+                        Ok(shortcut) => Ok(shortcut),
+                        Err(ShortcutError::NotFoundError(_)) => Err((StatusCode::NOT_FOUND, String::new())),
+                        Err(ShortcutError::MetadataError(e)) => {
+                            Err((StatusCode::BAD_REQUEST, format!("Invalid metadata provided for link: {e}")))
                         }
-                    } else {
-                        println!("\nNo links could be found")
+                        Err(_) => Err((StatusCode::INTERNAL_SERVER_ERROR, String::new())),
                     }
                 }
-            } else {
-                println!("\nThe links server has not been started. Use the start command to start the server");
+                false => {
+                    let json = serde_json::to_string(&edit_link).unwrap();
+                    match client.send(Method::PATCH, hash, Some(json)).await {
+                        Err(_) => return Err(CliError::NotRunning()),
+                        Ok((StatusCode::OK, body)) => serde_json::from_str::<db::Shortcut>(&body)
+                            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, String::new())),
+                        Ok(response) => Err(response),
+                    }
+                }
+            };
+
+            match edited {
+                Ok(shortcut) => {
+                    let url = utils::shortcut_url(&local_addr, &path, &shortcut.hash);
+                    Ok(Output::record(
+                        format!("Updated {url}"),
+                        vec![
+                            ("shortcut", url.into()),
+                            ("title", shortcut.title.into()),
+                            ("description", shortcut.description.into()),
+                            ("tags", shortcut.tags.into()),
+                        ],
+                    ))
+                }
+                Err((StatusCode::NOT_FOUND, _)) => {
+                    Err(CliError::NotFound("The given shortcut link does not exist".to_string()))
+                }
+                Err((StatusCode::BAD_REQUEST, reason)) => Err(CliError::InvalidInput(reason)),
+                Err(_) => Err(CliError::Failed("Could not edit given shortcut link".to_string())),
             }
         }
+        args::EntityType::Qr(qr_command) => {
+            let link = match qr_command.shortcut.contains("://") {
+                true => qr_command.shortcut,
+                false => utils::shortcut_url(&local_addr, &path, &qr_command.shortcut),
+            };
+            let qr_code = render_qr(&link, true, qr_command.file.as_deref())
+                .map_err(|e| CliError::Failed(format!("Could not create the QR code: {e}")))?;
+
+            Ok(Output::record(
+                qr_code.join("\n\n"),
+                vec![
+                    ("shortcut", link.into()),
+                    ("qr_file", qr_command.file.map(|f| f.display().to_string()).into()),
+                ],
+            ))
+        }
         args::EntityType::New(new_command) => {
-            let client = reqwest::Client::new();
+            let password = match (new_command.password, new_command.password_stdin) {
+                (false, false) => None,
+                (_, true) if new_command.from_file.as_deref() == Some(Path::new("-")) => {
+                    return Err(CliError::InvalidInput(
+                        "--password-stdin can't be used while reading links from stdin".to_string(),
+                    ))
+                }
+                (_, from_stdin) => Some(prompt::password(from_stdin).map_err(|e| {
+                    CliError::InvalidInput(format!("Could not read the password: {e}"))
+                })?),
+            };
+            if new_command.links.len() != 1 || new_command.from_file.is_some() {
+                return create_batch(&new_command, password, direct, &client, &local_addr, &path).await;
+            }
+
+            let link = utils::normalize_link(&new_command.links[0], new_command.strip_tracking).map_err(|_| {
+                CliError::InvalidInput(
+                    "The link given is not valid. Make sure to provide the full link address.".to_string(),
+                )
+            })?;
             let create_link = utils::CreateLink {
-                link: new_command.link,
-            };
-
-            if utils::is_url(&create_link.link) {
-                match client.post(format!("http://{local_addr}/{path}"))
-                    .json(&create_link)
-                    .send().await {
-                    Err(_) => println!("\nThe links server has not been started. Use the start command to start the server"),
-                    Ok(resp) => {
-                        match resp.status() {
-                            StatusCode::OK => {
-                                let hashed_link = resp.text().await.unwrap();
-                                println!("\n{} --> {}", hashed_link, create_link.link)
-                            },
-                            _ => println!("\nCould not create shortcut to link")
+                link,
+                strip_tracking: new_command.strip_tracking,
+                prefix: new_command.prefix,
+                password,
+                alias: None,
+                title: new_command.title,
+                description: new_command.description,
+                tags: new_command.tags,
+            };
+            let failed = || CliError::Failed("Could not create shortcut to link".to_string());
+
+            let hashed_link = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    match shortcuts::create_shortcut(&db_client, &table_name, &create_link, &audit::local_user()).await {
+                        Ok(shortcut) => utils::shortcut_url(&local_addr, &path, &shortcut.hash),
+                        Err(ShortcutError::MetadataError(e)) => {
+                            return Err(CliError::InvalidInput(format!("Invalid metadata provided for link: {e}")))
                         }
+                        Err(_) => return Err(failed()),
                     }
                 }
-            } else {
-                println!(
-                    "\nThe link given is not valid. Make sure to provide the full link address."
-                )
+                false => {
+                    let json = serde_json::to_string(&create_link).unwrap();
+
+                    match client.send(Method::POST, "", Some(json)).await {
+                        Err(_) => return Err(CliError::NotRunning()),
+                        Ok((StatusCode::OK, hashed_link)) => hashed_link,
+                        Ok((StatusCode::BAD_REQUEST, reason)) => return Err(CliError::InvalidInput(reason)),
+                        Ok(_) => return Err(failed()),
+                    }
+                }
+            };
+            let copied = new_command.copy && clipboard::copy(&hashed_link);
+
+            let mut message = vec![format!("{} --> {}", hashed_link, create_link.link)];
+            if copied {
+                message.push("Copied to the clipboard".to_string());
             }
+            let qr_code = render_qr(&hashed_link, new_command.qr, new_command.qr_file.as_deref())
+                .map_err(|e| {
+                    CliError::Failed(format!("Created {hashed_link}, but not its QR code: {e}"))
+                })?;
+            message.extend(qr_code);
+
+            Ok(Output::record(
+                message.join("\n\n"),
+                vec![
+                    ("shortcut", hashed_link.into()),
+                    ("link", create_link.link.into()),
+                    ("copied", copied.into()),
+                    ("qr_file", new_command.qr_file.map(|f| f.display().to_string()).into()),
+                ],
+            ))
         }
         args::EntityType::Delete(delete_command) => {
-            let client = reqwest::Client::new();
-            let hash = delete_command.link.split('/').last().unwrap();
+            let Some(shortcut) = &delete_command.shortcut else {
+                let filter = utils::DeleteFilter {
+                    link: delete_command.link,
+                    pattern: delete_command.pattern,
+                    older_than_secs: delete_command.older_than.map(|age| age.as_secs()),
+                    all: false,
+                };
+                confirm("Delete every shortcut matching the given filters?", delete_command.yes)?;
 
-            let parent_path= match path.as_str() {
-                "" => "".to_string(),
-                p => format!("{p}/")
+                let deleted = delete_matching(&filter, direct, &client).await?;
+                if deleted.is_empty() {
+                    return Err(CliError::NotFound("No shortcuts matched the given filters".to_string()));
+                }
+                return Ok(deleted_output(deleted));
+            };
+            let hash = utils::shortcut_hash(shortcut);
+
+            let deleted = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    match shortcuts::delete_shortcut(&db_client, &table_name, hash, &audit::local_user()).await {
+                        Ok(_) => Ok(()),
+                        Err(ShortcutError::NotFoundError(_)) => Err(StatusCode::BAD_REQUEST),
+                        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+                    }
+                }
+                false => match client.send(Method::DELETE, hash, None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::NO_CONTENT, _)) => Ok(()),
+                    Ok((status, _)) => Err(status),
+                },
             };
 
-            match client.delete(format!("http://{local_addr}/{parent_path}{hash}"))
-                .send().await {
-                Err(_) => println!("\nThe links server has not been started. Use the start command to start the server"),
-                Ok(resp) => {
-                    match resp.status() {
-                        StatusCode::NO_CONTENT => println!("\nDeleted shortcut to link"),
-                        _ => println!("\nCould not delete given shortcut link")
+            match deleted {
+                Ok(_) => Ok(Output::record(
+                    "Moved shortcut to the trash, use the restore command to undo",
+                    vec![("hash", hash.into())],
+                )),
+                Err(StatusCode::BAD_REQUEST) => {
+                    Err(CliError::NotFound("The given shortcut link does not exist".to_string()))
+                }
+                Err(_) => Err(CliError::Failed("Could not delete given shortcut link".to_string())),
+            }
+        }
+        args::EntityType::Trash => {
+            let shortcuts = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    shortcuts::list_trash(&db_client, &table_name).await.ok()
+                }
+                false => match client.send(Method::GET, "trash", None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => serde_json::from_str::<Vec<db::Shortcut>>(&body).ok(),
+                    Ok(_) => None,
+                },
+            };
+
+            match shortcuts {
+                None => Err(CliError::Failed("The trash could not be read".to_string())),
+                Some(shortcuts) => Ok(Output::records(
+                    vec!["link", "hash", "deleted_at"],
+                    shortcuts
+                        .into_iter()
+                        .map(|s| vec![s.link.into(), s.hash.into(), s.deleted_at.map(utils::format_time).into()])
+                        .collect(),
+                )
+                .or_message(format!(
+                    "The trash is empty. Deleted shortcuts stay here for {} days",
+                    args.trash_days
+                ))),
+            }
+        }
+        args::EntityType::Restore(restore_command) => {
+            let hash = utils::shortcut_hash(&restore_command.shortcut);
+
+            let restored = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    match shortcuts::restore_shortcut(&db_client, &table_name, hash, &audit::local_user()).await {
+                        Ok(shortcut) => Ok(shortcut),
+                        Err(ShortcutError::NotFoundError(_)) => Err(StatusCode::BAD_REQUEST),
+                        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
                     }
                 }
+                false => match client.send(Method::POST, &format!("trash/{hash}/restore"), None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => {
+                        serde_json::from_str::<db::Shortcut>(&body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                    Ok((status, _)) => Err(status),
+                },
+            };
+
+            match restored {
+                Ok(shortcut) => {
+                    let url = utils::shortcut_url(&local_addr, &path, &shortcut.hash);
+                    Ok(Output::record(
+                        format!("Restored {url} --> {}", shortcut.link),
+                        vec![("shortcut", url.into()), ("link", shortcut.link.into())],
+                    ))
+                }
+                Err(StatusCode::BAD_REQUEST) => {
+                    Err(CliError::NotFound("The given shortcut is not in the trash".to_string()))
+                }
+                Err(_) => Err(CliError::Failed("Could not restore given shortcut".to_string())),
+            }
+        }
+        args::EntityType::Audit(audit_command) => {
+            let filter = utils::AuditFilter {
+                hash: audit_command.shortcut.as_deref().map(|s| utils::shortcut_hash(s).to_string()),
+            };
+
+            let events = match direct {
+                true => {
+                    let (db_client, table_name) = direct_storage().await?;
+                    shortcuts::audit_log(&db_client, &table_name, filter.hash.as_deref()).await.ok()
+                }
+                false => match client.send(Method::GET, &filter.route(), None).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => serde_json::from_str::<Vec<db::AuditEvent>>(&body).ok(),
+                    Ok(_) => None,
+                },
+            };
+
+            match events {
+                None => Err(CliError::Failed("The audit log could not be read".to_string())),
+                Some(events) if events.is_empty() && filter.hash.is_some() => {
+                    Err(CliError::NotFound("No changes have been recorded for the given shortcut".to_string()))
+                }
+                Some(events) => Ok(Output::records(
+                    vec!["at", "hash", "action", "actor", "detail"],
+                    events
+                        .into_iter()
+                        .map(|e| {
+                            vec![
+                                utils::format_time(e.at).into(),
+                                e.hash.into(),
+                                e.action.as_str().into(),
+                                e.actor.into(),
+                                e.detail.into(),
+                            ]
+                        })
+                        .collect(),
+                )
+                .or_message("No changes have been recorded yet")),
+            }
+        }
+        args::EntityType::Clear(clear_command) => {
+            confirm("Delete all shortened links?", clear_command.yes)?;
+
+            let filter = utils::DeleteFilter {
+                all: true,
+                ..utils::DeleteFilter::default()
+            };
+            let deleted = delete_matching(&filter, direct, &client).await?;
+            Ok(deleted_output(deleted).or_message("There were no shortcuts to delete"))
+        }
+    }
+}
+
+/// Asks on the terminal before deleting many shortcuts, unless --yes was given
+fn confirm(question: &str, yes: bool) -> Result<(), CliError> {
+    if yes {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(CliError::InvalidInput(
+            "Pass --yes to delete the shortcuts without being asked".to_string(),
+        ));
+    }
+
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| CliError::Failed(format!("Could not read the answer: {e}")))?;
+
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(CliError::Failed("Nothing was deleted".to_string())),
+    }
+}
+
+/// Deletes every shortcut the filter matches, from storage or through the server
+async fn delete_matching(
+    filter: &utils::DeleteFilter,
+    direct: bool,
+    client: &AdminClient,
+) -> Result<Vec<db::Shortcut>, CliError> {
+    let failed = || CliError::Failed("Could not delete the shortcuts".to_string());
+
+    match direct {
+        true => {
+            let (db_client, table_name) = direct_storage().await?;
+            shortcuts::delete_shortcuts(&db_client, &table_name, filter, &audit::local_user())
+                .await
+                .map_err(|_| failed())
+        }
+        false => match client.send(Method::DELETE, &filter.route(), None).await {
+            Err(_) => Err(CliError::NotRunning()),
+            Ok((StatusCode::OK, body)) => serde_json::from_str(&body).map_err(|_| failed()),
+            Ok(_) => Err(failed()),
+        },
+    }
+}
+
+fn deleted_output(deleted: Vec<db::Shortcut>) -> Output {
+    Output::records(
+        vec!["link", "hash"],
+        deleted.into_iter().map(|s| vec![s.link.into(), s.hash.into()]).collect(),
+    )
+}
+
+/// Creates shortcuts for every link given to the new command in as few requests as
+/// possible, reporting how each one went
+async fn create_batch(
+    new_command: &args::NewCommand,
+    password: Option<String>,
+    direct: bool,
+    client: &AdminClient,
+    local_addr: &SocketAddr,
+    path: &str,
+) -> Result<Output, CliError> {
+    if new_command.copy || new_command.qr || new_command.qr_file.is_some() {
+        return Err(CliError::InvalidInput(
+            "--copy, --qr and --qr-file can only be used when creating a single link".to_string(),
+        ));
+    }
+
+    // Links given as arguments have no line number
+    let mut lines: Vec<(Option<usize>, String, Option<String>)> =
+        new_command.links.iter().map(|link| (None, link.to_string(), None)).collect();
+    if let Some(file) = &new_command.from_file {
+        let text = batch::read(file).map_err(|e| {
+            CliError::InvalidInput(format!("Could not read links from {}: {e}", file.display()))
+        })?;
+        lines.extend(batch::parse(&text).into_iter().map(|l| (Some(l.line), l.link, l.alias)));
+    }
+    if lines.is_empty() {
+        return Err(CliError::InvalidInput("No links were given".to_string()));
+    }
+
+    let create_links = lines
+        .iter()
+        .map(|(_, link, alias)| utils::CreateLink {
+            link: link.to_string(),
+            strip_tracking: new_command.strip_tracking,
+            prefix: new_command.prefix,
+            password: password.clone(),
+            alias: alias.clone(),
+            title: new_command.title.clone(),
+            description: new_command.description.clone(),
+            tags: new_command.tags.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let storage = match direct {
+        true => Some(direct_storage().await?),
+        false => None,
+    };
+
+    let mut results = Vec::with_capacity(create_links.len());
+    for chunk in create_links.chunks(shortcuts::MAX_BATCH_SIZE) {
+        match &storage {
+            Some((db_client, table_name)) => {
+                let created = shortcuts::create_shortcuts(db_client, table_name, chunk, &audit::local_user()).await;
+                results.extend(chunk.iter().zip(created).map(|(create_link, created)| match created {
+                    Ok(shortcut) => utils::BatchResult {
+                        shortcut: Some(utils::shortcut_url(local_addr, path, &shortcut.hash)),
+                        link: shortcut.link,
+                        error: None,
+                    },
+                    Err(e) => utils::BatchResult {
+                        link: create_link.link.to_string(),
+                        shortcut: None,
+                        error: Some(e.to_string()),
+                    },
+                }));
+            }
+            None => {
+                let json = serde_json::to_string(chunk).unwrap();
+                let created = match client.send(Method::POST, "batch", Some(json)).await {
+                    Err(_) => return Err(CliError::NotRunning()),
+                    Ok((StatusCode::OK, body)) => serde_json::from_str::<Vec<utils::BatchResult>>(&body)
+                        .map_err(|e| e.to_string()),
+                    Ok((status, body)) => Err(format!("{status}: {body}")),
+                };
+
+                // A request failing as a whole fails each of its links
+                match created {
+                    Ok(created) => results.extend(created),
+                    Err(e) => results.extend(chunk.iter().map(|create_link| utils::BatchResult {
+                        link: create_link.link.to_string(),
+                        shortcut: None,
+                        error: Some(format!("request failed: {e}")),
+                    })),
+                }
             }
         }
     }
+
+    let failed = results.iter().any(|result| result.error.is_some());
+    let rows = lines
+        .into_iter()
+        .zip(results)
+        .map(|((line, _, _), result)| {
+            vec![line.into(), result.link.into(), result.shortcut.into(), result.error.into()]
+        })
+        .collect();
+
+    Ok(Output::records(vec!["line", "link", "shortcut", "error"], rows).failed_if(failed))
+}
+
+fn start_detached(server_args: &[String]) -> Result<Output, CliError> {
+    // The server stores its addresses once it is listening
+    let modified = || std::fs::metadata("/tmp/cli_shortener.txt").and_then(|m| m.modified()).ok();
+    let before = modified();
+
+    match daemon::spawn_detached(server_args, || modified().is_some_and(|m| Some(m) != before)) {
+        Ok(pid) => {
+            let log = daemon::log_file().map(|l| l.display().to_string()).unwrap_or_default();
+            Ok(Output::record(
+                format!("Started the links server in the background (PID {pid}), logging to {log}"),
+                vec![("pid", pid.into()), ("log_file", log.into())],
+            ))
+        }
+        Err(DaemonError::AlreadyRunning(pid)) => Err(CliError::Failed(format!(
+            "The links server is already running in the background (PID {pid})"
+        ))),
+        Err(e) => {
+            let log = daemon::log_file().map(|l| l.display().to_string()).unwrap_or_default();
+            Err(CliError::Failed(format!("Could not start the links server: {e} ({log})")))
+        }
+    }
+}
+
+/// Draws the QR code of the link if `show` is set and saves it to `file` if given,
+/// returning what to show
+fn render_qr(link: &str, show: bool, file: Option<&Path>) -> Result<Vec<String>, qr::QrError> {
+    let mut shown = Vec::new();
+
+    if show {
+        shown.push(qr::terminal(link)?);
+    }
+    if let Some(file) = file {
+        qr::write_file(link, file)?;
+        shown.push(format!("Saved the QR code to {}", file.display()));
+    }
+
+    Ok(shown)
 }
 
-pub async fn init(args: ClapArgs) {
+/// Connects to the storage backend for managing links without the server
+async fn direct_storage() -> Result<(aws_sdk_dynamodb::Client, String), CliError> {
+    dotenv().ok();
+
+    match env::var("AWS_TABLE_NAME") {
+        Ok(table_name) => Ok((db::init_db_client().await, table_name)),
+        Err(_) => Err(CliError::InvalidInput(
+            "Set AWS_TABLE_NAME, or add it to a .env file, to manage links directly".to_string(),
+        )),
+    }
+}
+
+/// Address the server would listen on with the given options
+fn default_addr(args: &ClapArgs) -> SocketAddr {
+    listener::listen_addrs(args)
+        .into_iter()
+        .find_map(|addr| match addr {
+            ListenAddr::Tcp(addr) => Some(addr),
+            ListenAddr::Unix(_) => None,
+        })
+        .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, args.port)))
+}
+
+pub async fn init(args: ClapArgs) -> ExitCode {
     dotenv().ok();
     let db_client = db::init_db_client().await;
     let db_table_name = env::var("AWS_TABLE_NAME").unwrap();
 
-    tracing_subscriber::fmt()
-        .with_max_level(if args.verbose {
-            tracing::Level::DEBUG
-        } else {
-            tracing::Level::INFO
-        })
-        .compact()
-        .init();
+    telemetry::init_tracing(&args);
+
+    // Use the sockets systemd opened for us if socket activated, otherwise bind each
+    // address once and keep that socket for the server
+    let activated_listeners = service::activated_listeners();
+    let socket_activated = !activated_listeners.is_empty();
+    let listeners = match socket_activated {
+        true => activated_listeners,
+        false => match listener::bind_all(&listener::listen_addrs(&args), args.strict_port) {
+            Ok(listeners) => listeners,
+            Err(e) => {
+                tracing::error!("Cannot start server: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    let local_addrs = listeners
+        .iter()
+        .filter_map(|listener| listener.local_addr().ok())
+        .collect::<Vec<_>>();
 
-    let addr = gen_addr(&args);
+    // Links shown by the server use its public URL, or else its first TCP address
+    let addr = local_addrs
+        .iter()
+        .find_map(|local_addr| match local_addr {
+            ListenAddr::Tcp(addr) => Some(*addr),
+            ListenAddr::Unix(_) => None,
+        })
+        .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::LOCALHOST, args.port)));
+    let base_url = args.public_url.clone().unwrap_or_else(|| format!("http://{addr}"));
     let path = args.path.as_str();
     let parent_path= match args.path.as_str() {
         "" => "".to_string(),
@@ -139,79 +869,360 @@ pub async fn init(args: ClapArgs) {
     };
 
 
-    let app = axum::Router::new()
+    let tokens = match &args.rate_limit_tokens {
+        Some(path) => match rate_limit::read_tokens(path) {
+            Ok(tokens) => Arc::new(tokens),
+            Err(e) => {
+                tracing::error!("Cannot read rate limit tokens from {}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Arc::default(),
+    };
+    let create_limit = middleware::from_fn_with_state(
+        RateLimiter::new("create", args.create_limit, tokens.clone()),
+        rate_limit::limit,
+    );
+    let delete_limit = middleware::from_fn_with_state(
+        RateLimiter::new("delete", args.delete_limit, tokens.clone()),
+        rate_limit::limit,
+    );
+    let redirect_limit = middleware::from_fn_with_state(
+        RateLimiter::new("redirect", args.redirect_limit, tokens),
+        rate_limit::limit,
+    );
+
+    let redirect_routes = axum::Router::new()
+        .route(&format!("/{parent_path}healthz"), routing::get(controller::healthz))
+        .route(&format!("/{parent_path}:hash"), routing::get(controller::open_shortcut).layer(redirect_limit.clone()))
+        .route(&format!("/{parent_path}:hash"), routing::post(controller::unlock_shortcut).layer(redirect_limit.clone()))
+        // Takes precedence over forwarding a path of "qr" for prefix and template shortcuts
+        .route(&format!("/{parent_path}:hash/qr"), routing::get(controller::qr_code).layer(redirect_limit.clone()))
+        .route(&format!("/{parent_path}:hash/*rest"), routing::get(controller::open_shortcut).layer(redirect_limit.clone()))
+        .route(&format!("/{parent_path}:hash/*rest"), routing::post(controller::unlock_shortcut).layer(redirect_limit));
+    // Traffic, the version and the number of links are only shown where links are managed
+    let status_routes = axum::Router::new()
+        .route(&format!("/{parent_path}metrics"), routing::get(metrics::get_metrics))
+        .route(&format!("/{parent_path}readyz"), routing::get(controller::readyz));
+    let admin_routes = axum::Router::new()
         .route(&format!("/{path}"), routing::get(controller::index))
-        .route(&format!("/{path}"), routing::post(controller::create_new_shortcut))
+        .route(&format!("/{path}"), routing::post(controller::create_new_shortcut).layer(create_limit.clone()))
+        .route(&format!("/{parent_path}batch"), routing::post(controller::create_new_shortcuts).layer(create_limit))
         .route(&format!("/{parent_path}all"), routing::get(controller::get_all_shortcuts))
-        .route(&format!("/{parent_path}:hash"), routing::get(controller::open_shortcut))
-        .route(&format!("/{parent_path}:hash"), routing::delete(controller::delete_shortcut))
-        .with_state((db_client, db_table_name, addr, path.to_string()));
+        .route(&format!("/{parent_path}search"), routing::get(controller::search_shortcuts))
+        .route(&format!("/{parent_path}all"), routing::delete(controller::delete_shortcuts).layer(delete_limit.clone()))
+        .route(&format!("/{parent_path}:hash"), routing::delete(controller::delete_shortcut).layer(delete_limit))
+        .route(&format!("/{parent_path}:hash"), routing::patch(controller::edit_shortcut))
+        .route(&format!("/{parent_path}trash"), routing::get(controller::get_trash))
+        .route(&format!("/{parent_path}trash/:hash/restore"), routing::post(controller::restore_shortcut))
+        .route(&format!("/{parent_path}audit"), routing::get(controller::get_audit_log));
 
-    let binding = axum::Server::try_bind(&addr);
+    let access_log = match &args.access_log {
+        None => None,
+        Some(target) => {
+            match AccessLog::open(target, args.access_log_format, args.access_log_max_size) {
+                Ok(access_log) => Some(access_log),
+                Err(e) => {
+                    tracing::error!("Cannot open access log {target}: {e:?}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    };
 
-    match binding {
-        Err(_) => tracing::error!("Cannot attach server to address {}", addr),
-        Ok(b) => {
-            let server = b.serve(app.into_make_service());
-            let local_addr = server.local_addr();
+    let started = Instant::now();
+    let failed_attempts = Arc::new(utils::FailedAttempts::default());
+    let cache = ShortcutCache::new(args.cache_size, Duration::from_secs(args.cache_ttl));
+    let link_count = Arc::new(cache::LinkCount::default());
+    let state = (db_client, db_table_name, base_url, path.to_string());
 
-            tracing::info!("Started on: http://{local_addr}/{path}");
+    if args.trash_days > 0 {
+        let retention = Duration::from_secs(args.trash_days * 24 * 60 * 60);
+        tokio::spawn(purge_trash(state.0.clone(), state.1.clone(), retention));
+    }
+    tokio::spawn(index_shortcuts(state.0.clone(), state.1.clone()));
+
+    let build_app = |routes: axum::Router<(aws_sdk_dynamodb::Client, String, String, String)>| {
+        let app = routes
+            .route_layer(middleware::from_fn(metrics::track_requests))
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(telemetry::request_span)
+                    .on_response(telemetry::record_response),
+            )
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(Extension(started))
+            .layer(Extension(failed_attempts.clone()))
+            .layer(Extension(cache.clone()))
+            .layer(Extension(link_count.clone()))
+            .with_state(state.clone());
+
+        match &access_log {
+            None => app,
+            Some(access_log) => app.layer(middleware::from_fn_with_state(
+                access_log.clone(),
+                access_log::log_requests,
+            )),
+        }
+    };
 
-            if store_local_addr(&local_addr, &args.path).is_err() {
-                tracing::error!("Could not store local address of server");
+    // With an admin socket, links can only be managed through it and TCP only redirects
+    let (app, admin) = match &args.admin_socket {
+        None => (build_app(redirect_routes.merge(status_routes).merge(admin_routes)), None),
+        Some(socket_path) => match listener::bind_admin_socket(socket_path, args.admin_socket_mode) {
+            Ok(admin_listener) => (
+                build_app(
+                    redirect_routes
+                        .clone()
+                        .route(&format!("/{parent_path}readyz"), routing::get(controller::ready)),
+                ),
+                Some((admin_listener, build_app(redirect_routes.merge(status_routes).merge(admin_routes)))),
+            ),
+            Err(e) => {
+                tracing::error!("Cannot start server: {e}");
+                return ExitCode::FAILURE;
             }
+        },
+    };
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut servers = JoinSet::new();
+
+    let listeners = listeners
+        .into_iter()
+        .map(|listener| (listener, app.clone()))
+        .chain(admin);
+
+    for (listener, app) in listeners {
+        if let Err(e) = serve(&mut servers, listener, app, wait_for_shutdown(shutdown_rx.clone())) {
+            tracing::error!("Cannot attach server to listener: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    }
 
-            if server.await.is_err() {
+    for local_addr in &local_addrs {
+        match local_addr {
+            ListenAddr::Tcp(addr) => tracing::info!("Started on: http://{addr}/{path}"),
+            ListenAddr::Unix(_) => tracing::info!("Started on: {local_addr}"),
+        }
+    }
+    if let Some(socket_path) = &args.admin_socket {
+        tracing::info!("Managing links through: unix:{}", socket_path.display());
+    }
+
+    if store_local_addrs(&local_addrs, args.admin_socket.as_deref(), &args.path).is_err() {
+        tracing::error!("Could not store local address of server");
+    }
+
+    let drain_timeout = Duration::from_secs(args.drain_timeout);
+    let stopped = async {
+        let mut exit_code = ExitCode::SUCCESS;
+        while let Some(result) = servers.join_next().await {
+            if !matches!(result, Ok(Ok(()))) {
                 tracing::error!("Server stopped unexpectedly");
+                exit_code = ExitCode::FAILURE;
+            }
+        }
+        exit_code
+    };
+    let drain = async {
+        shutdown_signal().await;
+        tracing::info!("Shutting down, waiting for in-flight requests to finish");
+        shutdown_tx.send(()).ok();
+        tokio::time::sleep(drain_timeout).await;
+    };
+
+    let exit_code = tokio::select! {
+        exit_code = stopped => exit_code,
+        _ = drain => {
+            tracing::error!("Requests still running after {}s, stopping anyway", args.drain_timeout);
+            ExitCode::FAILURE
+        }
+    };
+
+    if remove_local_addrs(&local_addrs, args.admin_socket.as_deref(), &args.path).is_err() {
+        tracing::error!("Could not remove local address of server");
+    }
+    // Sockets opened by systemd are its to clean up
+    if !socket_activated {
+        for local_addr in &local_addrs {
+            if let ListenAddr::Unix(socket_path) = local_addr {
+                std::fs::remove_file(socket_path).ok();
             }
         }
     }
+    if let Some(socket_path) = &args.admin_socket {
+        std::fs::remove_file(socket_path).ok();
+    }
+    daemon::remove_pid_file();
+    telemetry::shutdown_tracing();
+    tracing::info!("Stopped server");
+    exit_code
 }
 
-fn gen_addr(args: &ClapArgs) -> SocketAddr {
-    let mut addr = SocketAddr::from((
-        args.host
-            .parse::<IpAddr>()
-            .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
-        args.port,
-    ));
-    let addr_str = format!("{}:{}", args.host, args.port);
-    let listener = std::net::TcpListener::bind(&addr_str);
+/// Serves the app on the listener in the background until `shutdown` completes
+fn serve(
+    servers: &mut JoinSet<Result<(), hyper::Error>>,
+    listener: Listener,
+    app: axum::Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    match listener {
+        Listener::Tcp(listener) => {
+            servers.spawn(
+                axum::Server::from_tcp(listener)
+                    .map_err(std::io::Error::other)?
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(shutdown),
+            );
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            let listener = tokio::net::UnixListener::from_std(listener)?;
+            // Clients on the socket are local, so they count as loopback for rate limits
+            // and the access log
+            let app = app.layer(Extension(ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))));
+            servers.spawn(
+                axum::Server::builder(listener::UnixAccept(listener))
+                    .serve(app.into_make_service_with_connect_info::<listener::UnixPeer>())
+                    .with_graceful_shutdown(shutdown),
+            );
+        }
+    }
+    Ok(())
+}
 
-    if listener.is_err() {
-        tracing::error!(
-            "Cannot bind to address {}, generating random port",
-            addr_str
-        );
-        addr.set_port(0);
+/// How often the trash is checked for shortcuts past their retention period
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Purges shortcuts which have been in the trash for longer than `retention`, for as
+/// long as the server runs
+async fn purge_trash(client: aws_sdk_dynamodb::Client, table_name: String, retention: Duration) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match shortcuts::purge_trash(&client, &table_name, retention.as_secs()).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Purged {purged} shortcuts from the trash"),
+            Err(e) => tracing::error!("Could not purge the trash: {e:?}"),
+        }
+    }
+}
+
+/// Adds shortcuts stored before the search index existed to it, once at startup
+async fn index_shortcuts(client: aws_sdk_dynamodb::Client, table_name: String) {
+    match shortcuts::index_shortcuts(&client, &table_name).await {
+        Ok(0) => {}
+        Ok(indexed) => tracing::info!("Added {indexed} shortcuts to the search index"),
+        Err(e) => tracing::error!("Could not add shortcuts to the search index: {e:?}"),
     }
-    std::mem::drop(listener);
+}
 
-    addr
+/// Completes once the server is told to shut down
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<()>) {
+    shutdown.changed().await.ok();
 }
 
-fn store_local_addr(addr: &SocketAddr, path: &str) -> Result<(), std::io::Error> {
+/// Contents of the address file: the server path, every address it listens on and the
+/// admin socket if there is one
+fn local_addrs_contents(addrs: &[ListenAddr], admin_socket: Option<&Path>, path: &str) -> String {
+    let mut contents = format!("{path}\n");
+    for addr in addrs {
+        contents.push_str(&format!("{addr}\n"));
+    }
+    if let Some(admin_socket) = admin_socket {
+        contents.push_str(&format!("admin {}\n", admin_socket.display()));
+    }
+    contents
+}
+
+fn store_local_addrs(
+    addrs: &[ListenAddr],
+    admin_socket: Option<&Path>,
+    path: &str,
+) -> Result<(), std::io::Error> {
     let mut file = File::create("/tmp/cli_shortener.txt")?;
-    file.write_all(format!("{}\n{}\n{}", &addr.ip(), &addr.port(), &path).as_bytes())?;
+    file.write_all(local_addrs_contents(addrs, admin_socket, path).as_bytes())?;
     Ok(())
 }
 
-fn get_local_addr() -> Result<(SocketAddr, String), std::io::Error> {
+/// Removes the stored addresses if they still point at this server, so clients don't try
+/// to reach it after it has stopped
+fn remove_local_addrs(
+    addrs: &[ListenAddr],
+    admin_socket: Option<&Path>,
+    path: &str,
+) -> Result<(), std::io::Error> {
+    match std::fs::read_to_string("/tmp/cli_shortener.txt") {
+        Ok(contents) if contents == local_addrs_contents(addrs, admin_socket, path) => {
+            std::fs::remove_file("/tmp/cli_shortener.txt")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Completes once SIGINT (Ctrl+C) or SIGTERM is received
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if signal::ctrl_c().await.is_err() {
+            tracing::error!("Could not listen for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => {
+                tracing::error!("Could not listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Reads the address file, returning the server's TCP address if it has one, the socket
+/// to manage links through and the path. A server listening only on Unix sockets is
+/// reached through the first of them when it has no admin socket
+fn get_local_addr() -> Result<(Option<SocketAddr>, Option<PathBuf>, String), std::io::Error> {
     let mut file = File::open("/tmp/cli_shortener.txt")?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    let values = contents.split_whitespace().collect::<Vec<&str>>();
+    let mut values = contents.lines();
 
-    let host = values[0]
-        .parse::<IpAddr>()
-        .map_err(|_| std::io::Error::last_os_error())?;
-    let port = values[1]
-        .parse::<u16>()
-        .map_err(|_| std::io::Error::last_os_error())?;
-    let path = values[2]
-        .parse::<String>()
-        .map_err(|_| std::io::Error::last_os_error())?;
+    let path = values.next().unwrap_or("").to_string();
+    let values = values.collect::<Vec<_>>();
+    let addrs = values.iter().filter_map(|v| v.parse::<ListenAddr>().ok()).collect::<Vec<_>>();
+    let addr = addrs.iter().find_map(|addr| match addr {
+        ListenAddr::Tcp(addr) => Some(*addr),
+        ListenAddr::Unix(_) => None,
+    });
+    let unix_socket = addrs.iter().find_map(|addr| match addr {
+        ListenAddr::Unix(path) => Some(path.clone()),
+        ListenAddr::Tcp(_) => None,
+    });
+    let admin_socket = values
+        .iter()
+        .find_map(|v| v.strip_prefix("admin "))
+        .map(PathBuf::from);
 
-
-    Ok((SocketAddr::from((host, port)), path))
+    match (addr, admin_socket) {
+        (None, None) => match unix_socket {
+            Some(socket) => Ok((None, Some(socket), path)),
+            None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no server address stored")),
+        },
+        (addr, admin_socket) => Ok((addr, admin_socket, path)),
+    }
 }
@@ -0,0 +1,184 @@
+use std::{
+    future::Future,
+    sync::LazyLock,
+    time::Instant,
+};
+
+use axum::{
+    extract::MatchedPath,
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::db::DbError;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub redirects: IntCounter,
+    pub creations: IntCounter,
+    pub deletions: IntCounter,
+    pub db_duration: HistogramVec,
+    pub db_errors: IntCounterVec,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("shortener".to_string()), None)
+            .expect("Could not create metrics registry");
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["route", "method"],
+        )
+        .unwrap();
+        let redirects = IntCounter::new("redirects_total", "Shortcuts redirected").unwrap();
+        let creations = IntCounter::new("shortcuts_created_total", "Shortcuts created").unwrap();
+        let deletions = IntCounter::new("shortcuts_deleted_total", "Shortcuts deleted").unwrap();
+        let db_duration = HistogramVec::new(
+            HistogramOpts::new("db_request_duration_seconds", "Storage call latency by operation"),
+            &["operation"],
+        )
+        .unwrap();
+        let db_errors = IntCounterVec::new(
+            Opts::new("db_errors_total", "Storage call errors by operation and error"),
+            &["operation", "error"],
+        )
+        .unwrap();
+        let cache_hits = IntCounter::new("cache_hits_total", "Redirects served from the cache").unwrap();
+        let cache_misses = IntCounter::new("cache_misses_total", "Redirects which went to the db").unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+        registry.register(Box::new(redirects.clone())).unwrap();
+        registry.register(Box::new(creations.clone())).unwrap();
+        registry.register(Box::new(deletions.clone())).unwrap();
+        registry.register(Box::new(db_duration.clone())).unwrap();
+        registry.register(Box::new(db_errors.clone())).unwrap();
+        registry.register(Box::new(cache_hits.clone())).unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            request_duration,
+            redirects,
+            creations,
+            deletions,
+            db_duration,
+            db_errors,
+            cache_hits,
+            cache_misses,
+        }
+    }
+}
+
+/// Times a storage call and counts any error by its variant. Data that doesn't exist is
+/// an answer rather than a failure, so it isn't counted
+pub async fn track_db<T>(
+    operation: &str,
+    call: impl Future<Output = Result<T, DbError>>,
+) -> Result<T, DbError> {
+    let started = Instant::now();
+    let result = call.await;
+
+    METRICS
+        .db_duration
+        .with_label_values(&[operation])
+        .observe(started.elapsed().as_secs_f64());
+    match &result {
+        Ok(_) | Err(DbError::NotFoundError(_)) => {}
+        Err(e) => METRICS
+            .db_errors
+            .with_label_values(&[operation, e.variant_name()])
+            .inc(),
+    }
+
+    result
+}
+
+/// Middleware counting requests and their latency by matched route
+pub async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let method = request.method().to_string();
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    METRICS
+        .request_duration
+        .with_label_values(&[&route, &method])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .requests
+        .with_label_values(&[&route, &method, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+pub async fn get_metrics() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        Ok(_) => (
+            [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+            buffer,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Could not encode metrics: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Could not encode metrics").into_response()
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_track_db() {
+    let result = track_db("test_op", async { Err::<(), DbError>(DbError::DuplicationError()) }).await;
+    assert!(result.is_err());
+
+    assert_eq!(
+        METRICS
+            .db_errors
+            .with_label_values(&["test_op", "duplication"])
+            .get(),
+        1
+    );
+    assert_eq!(
+        METRICS
+            .db_duration
+            .with_label_values(&["test_op"])
+            .get_sample_count(),
+        1
+    );
+
+    let result = track_db("test_op", async { Err::<(), DbError>(DbError::NotFoundError("x".to_string())) }).await;
+    assert!(result.is_err());
+    assert_eq!(
+        METRICS
+            .db_errors
+            .with_label_values(&["test_op", "not_found"])
+            .get(),
+        0
+    );
+}
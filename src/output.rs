@@ -0,0 +1,200 @@
+use cli_table::{print_stdout, Cell, CellStruct, Style, Table};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::args::OutputFormat;
+
+/// Why a command failed, printed to stderr and turned into the exit code
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("The links server has not been started. Use the start command to start the server")]
+    NotRunning(),
+
+    #[error("{0}")]
+    InvalidInput(String),
+
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl CliError {
+    /// 1 when the command failed, 2 for invalid input like clap's usage errors, 3 when
+    /// the server is not running and 4 when there is nothing to act on
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Failed(_) => 1,
+            CliError::InvalidInput(_) => 2,
+            CliError::NotRunning() => 3,
+            CliError::NotFound(_) => 4,
+        }
+    }
+
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Table => eprintln!("\n{self}"),
+            OutputFormat::Json => eprintln!(
+                "{}",
+                serde_json::json!({ "error": self.to_string(), "code": self.exit_code() })
+            ),
+            OutputFormat::Csv | OutputFormat::Plain => eprintln!("{self}"),
+        }
+    }
+}
+
+/// What a command reports, printed in the format chosen with --output
+pub struct Output {
+    /// Shown instead of the records in the table format, which is meant for people
+    message: Option<String>,
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<Value>>,
+    /// Printed as a single JSON object rather than a list
+    single: bool,
+    /// Some of the records are failures, such as links of a batch that were not created
+    failed: bool,
+}
+
+impl Output {
+    /// One record, described by `message` in the table format
+    pub fn record(message: impl Into<String>, fields: Vec<(&'static str, Value)>) -> Output {
+        let (columns, row) = fields.into_iter().unzip();
+        Output {
+            message: Some(message.into()),
+            columns,
+            rows: vec![row],
+            single: true,
+            failed: false,
+        }
+    }
+
+    /// A list of records, shown as a table
+    pub fn records(columns: Vec<&'static str>, rows: Vec<Vec<Value>>) -> Output {
+        Output {
+            message: None,
+            columns,
+            rows,
+            single: false,
+            failed: false,
+        }
+    }
+
+    /// Message shown in the table format when there are no records
+    pub fn or_message(mut self, message: impl Into<String>) -> Output {
+        if self.rows.is_empty() {
+            self.message = Some(message.into());
+        }
+        self
+    }
+
+    /// Exits with 1 after printing the records if any of them failed
+    pub fn failed_if(mut self, failed: bool) -> Output {
+        self.failed = failed;
+        self
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self.failed {
+            true => 1,
+            false => 0,
+        }
+    }
+
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Table => match &self.message {
+                Some(message) => println!("\n{message}"),
+                None => {
+                    let table = self
+                        .rows
+                        .iter()
+                        .map(|row| row.iter().map(|v| text(v).cell()).collect())
+                        .collect::<Vec<Vec<CellStruct>>>()
+                        .table()
+                        .title(self.columns.iter().map(|c| title(c).cell().bold(true)))
+                        .bold(true);
+
+                    if print_stdout(table).is_err() {
+                        eprintln!("\nCould not show the table");
+                    }
+                }
+            },
+            OutputFormat::Json => {
+                let mut objects = self.rows.iter().map(|row| {
+                    self.columns
+                        .iter()
+                        .map(|c| c.to_string())
+                        .zip(row.iter().cloned())
+                        .collect::<Map<String, Value>>()
+                });
+                let json = match self.single {
+                    true => objects.next().map(Value::Object).unwrap_or_default(),
+                    false => Value::Array(objects.map(Value::Object).collect()),
+                };
+                println!("{json}");
+            }
+            OutputFormat::Csv => {
+                println!("{}", self.columns.join(","));
+                for row in &self.rows {
+                    let fields = row.iter().map(|v| csv_field(&text(v))).collect::<Vec<_>>();
+                    println!("{}", fields.join(","));
+                }
+            }
+            OutputFormat::Plain => {
+                for row in &self.rows {
+                    let fields = row.iter().map(text).collect::<Vec<_>>();
+                    println!("{}", fields.join("\t"));
+                }
+            }
+        }
+    }
+}
+
+/// A value as text, with strings unquoted, nulls left empty and lists comma separated
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => s.to_string(),
+        Value::Array(values) => values.iter().map(text).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+/// Column name as a table title, e.g. "link_count" as "Link Count"
+fn title(column: &str) -> String {
+    column
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes a CSV field if it holds a separator, quote or line break
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[test]
+fn test_output_fields() {
+    assert_eq!(csv_field("https://example.com"), "https://example.com");
+    assert_eq!(csv_field("https://example.com/?a=1,2"), "\"https://example.com/?a=1,2\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+
+    assert_eq!(title("link"), "Link");
+    assert_eq!(title("uptime_secs"), "Uptime Secs");
+
+    assert_eq!(text(&Value::Null), "");
+    assert_eq!(text(&Value::from("abc")), "abc");
+    assert_eq!(text(&Value::from(42)), "42");
+    assert_eq!(text(&Value::from(vec!["infra", "web"])), "infra, web");
+}
@@ -0,0 +1,66 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Asks for a password on the terminal without echoing it, twice to catch typos, or reads
+/// the first line of stdin when `from_stdin` is set. Empty passwords are refused
+pub fn password(from_stdin: bool) -> io::Result<String> {
+    let password = match from_stdin {
+        true => read_line()?,
+        false if !io::stdin().is_terminal() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stdin is not a terminal, use --password-stdin to pipe the password in",
+            ))
+        }
+        false => {
+            let password = read_hidden("Password: ")?;
+            if read_hidden("Repeat password: ")? != password {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the passwords do not match"));
+            }
+            password
+        }
+    };
+
+    match password.is_empty() {
+        true => Err(io::Error::new(io::ErrorKind::InvalidInput, "the password is empty")),
+        false => Ok(password),
+    }
+}
+
+fn read_line() -> io::Result<String> {
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(unix)]
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    use std::os::fd::AsRawFd;
+
+    eprint!("{prompt}");
+    io::stderr().flush()?;
+
+    let fd = io::stdin().as_raw_fd();
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr fills in the termios struct when it returns 0
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let original = unsafe { termios.assume_init() };
+
+    // Hide what is typed but still move to a new line on enter
+    let mut hidden = original;
+    hidden.c_lflag &= !libc::ECHO;
+    hidden.c_lflag |= libc::ECHONL;
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &hidden) };
+    let line = read_line();
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    line
+}
+
+#[cfg(not(unix))]
+fn read_hidden(prompt: &str) -> io::Result<String> {
+    eprint!("{prompt}");
+    io::stderr().flush()?;
+    read_line()
+}
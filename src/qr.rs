@@ -0,0 +1,219 @@
+use std::{fmt::Write, fs, io, path::Path};
+
+use qrcode::{render::unicode, Color, EcLevel, QrCode};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Limits on the requested image size in pixels, and on the margin in modules
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 4096;
+const MAX_MARGIN: u32 = 16;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum QrError {
+    #[error("link could not be encoded as a QR code")]
+    EncodeError(String),
+
+    #[error("QR code image could not be created")]
+    ImageError(String),
+
+    #[error("QR code files must end in .png or .svg")]
+    FormatError(String),
+
+    #[error("QR code file could not be written")]
+    IoError(#[from] io::Error),
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// Error correction level, from L recovering 7% of the code up to H recovering 30%
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Ecc {
+    #[serde(alias = "l")]
+    L,
+    #[default]
+    #[serde(alias = "m")]
+    M,
+    #[serde(alias = "q")]
+    Q,
+    #[serde(alias = "h")]
+    H,
+}
+
+/// How a QR code image is drawn, also read from the query of the QR code endpoint
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct QrOptions {
+    pub format: QrFormat,
+
+    /// Width and height of the image in pixels. PNGs are drawn with whole pixels per
+    /// module, so they may come out slightly smaller
+    pub size: u32,
+
+    pub ecc: Ecc,
+
+    /// Modules of blank space around the code, which scanners need to find it
+    pub margin: u32,
+}
+
+impl Default for QrOptions {
+    fn default() -> QrOptions {
+        QrOptions {
+            format: QrFormat::Png,
+            size: 256,
+            ecc: Ecc::M,
+            margin: 4,
+        }
+    }
+}
+
+fn encode(data: &str, ecc: Ecc) -> Result<QrCode, QrError> {
+    let level = match ecc {
+        Ecc::L => EcLevel::L,
+        Ecc::M => EcLevel::M,
+        Ecc::Q => EcLevel::Q,
+        Ecc::H => EcLevel::H,
+    };
+    QrCode::with_error_correction_level(data.as_bytes(), level)
+        .map_err(|e| QrError::EncodeError(e.to_string()))
+}
+
+/// QR code drawn with half blocks, inverted so it scans on dark terminal backgrounds
+pub fn terminal(data: &str) -> Result<String, QrError> {
+    Ok(encode(data, Ecc::M)?
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Draws the QR code in the format of the options
+pub fn image(data: &str, options: &QrOptions) -> Result<Vec<u8>, QrError> {
+    match options.format {
+        QrFormat::Png => png(data, options),
+        QrFormat::Svg => svg(data, options).map(String::into_bytes),
+    }
+}
+
+pub fn svg(data: &str, options: &QrOptions) -> Result<String, QrError> {
+    let code = encode(data, options.ecc)?;
+    let width = code.width();
+    let margin = options.margin.min(MAX_MARGIN) as usize;
+    let size = options.size.clamp(MIN_SIZE, MAX_SIZE);
+    let modules = width + 2 * margin;
+
+    // One unit square per dark module, scaled up to the image size by the view box
+    let mut path = String::new();
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            write!(path, "M{},{}h1v1h-1z", i % width + margin, i / width + margin).ok();
+        }
+    }
+
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" \
+         viewBox=\"0 0 {modules} {modules}\" shape-rendering=\"crispEdges\">\
+         <rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>\
+         <path fill=\"#000\" d=\"{path}\"/></svg>\n"
+    ))
+}
+
+/// Grayscale PNG of the QR code, with each module drawn as a square of pixels
+pub fn png(data: &str, options: &QrOptions) -> Result<Vec<u8>, QrError> {
+    let code = encode(data, options.ecc)?;
+    let width = code.width();
+    let margin = options.margin.min(MAX_MARGIN) as usize;
+    let modules = width + 2 * margin;
+    let scale = (options.size.clamp(MIN_SIZE, MAX_SIZE) as usize / modules).max(1);
+    let size = modules * scale;
+
+    let mut pixels = vec![255u8; size * size];
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Light {
+            continue;
+        }
+        let x = (i % width + margin) * scale;
+        let y = (i / width + margin) * scale;
+        for row in y..y + scale {
+            pixels[row * size + x..row * size + x + scale].fill(0);
+        }
+    }
+
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(|e| QrError::ImageError(e.to_string()))?;
+
+    Ok(image)
+}
+
+/// Saves the QR code as a PNG or SVG image, going by the file extension
+pub fn write_file(data: &str, path: &Path) -> Result<(), QrError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let format = match extension.as_deref() {
+        Some("png") => QrFormat::Png,
+        Some("svg") => QrFormat::Svg,
+        _ => return Err(QrError::FormatError(path.display().to_string())),
+    };
+    let options = QrOptions {
+        format,
+        size: 512,
+        ..QrOptions::default()
+    };
+
+    fs::write(path, image(data, &options)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_qr_codes() {
+    let link = "http://127.0.0.1:8080/happy-cat";
+
+    assert!(terminal(link).unwrap().contains('█'));
+
+    let svg_options = QrOptions {
+        format: QrFormat::Svg,
+        size: 300,
+        margin: 2,
+        ..QrOptions::default()
+    };
+    let modules = encode(link, Ecc::M).unwrap().width() + 4;
+    let image = svg(link, &svg_options).unwrap();
+    assert!(image.contains("width=\"300\""));
+    assert!(image.contains(&format!("viewBox=\"0 0 {modules} {modules}\"")));
+
+    let image = png(link, &QrOptions::default()).unwrap();
+    assert!(image.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    let decoder = png::Decoder::new(image.as_slice());
+    let info = decoder.read_info().unwrap().info().clone();
+    let modules = encode(link, Ecc::M).unwrap().width() + 8;
+    assert_eq!(info.width as usize, 256 / modules * modules);
+
+    // Higher error correction needs more modules for the same link
+    let low = encode(link, Ecc::L).unwrap().width();
+    let high = encode(link, Ecc::H).unwrap().width();
+    assert!(high > low);
+
+    assert!(matches!(
+        write_file(link, Path::new("code.jpg")),
+        Err(QrError::FormatError(_))
+    ));
+}
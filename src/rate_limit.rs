@@ -0,0 +1,154 @@
+use std::{
+    collections::HashSet,
+    io,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use lru::LruCache;
+
+/// Most clients tracked at once. Past this, the one seen least recently is dropped, so
+/// rotating addresses can't grow the map without bound
+const MAX_CLIENTS: usize = 4096;
+
+/// Token bucket per client, refilled evenly over a minute
+pub struct RateLimiter {
+    name: &'static str,
+    per_minute: u32,
+    tokens: Arc<HashSet<String>>,
+    buckets: Mutex<LruCache<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `per_minute` requests per client, or unlimited if 0.
+    /// Requests with one of the bearer `tokens` are limited per token instead of per IP
+    pub fn new(name: &'static str, per_minute: u32, tokens: Arc<HashSet<String>>) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter {
+            name,
+            per_minute,
+            tokens,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CLIENTS).unwrap())),
+        })
+    }
+
+    /// Takes a token for the client, or returns how long until one is available
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let capacity = self.per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let (tokens, last) = buckets.get_or_insert_mut(key.to_string(), || (capacity, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * refill_per_sec).min(capacity);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - *tokens) / refill_per_sec))
+        }
+    }
+
+    /// Key of the client's bucket: its bearer token if the server knows it, or else its IP
+    /// address, as unknown tokens could be made up to get a fresh budget each request
+    fn client_key<B>(&self, request: &Request<B>, remote: &SocketAddr) -> String {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .filter(|token| self.tokens.contains(*token));
+
+        match token {
+            Some(token) => format!("token:{token}"),
+            None => format!("ip:{}", remote.ip()),
+        }
+    }
+}
+
+/// Reads the bearer tokens given their own rate limits, one per line
+pub fn read_tokens(path: &Path) -> io::Result<HashSet<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Middleware rejecting requests over budget with 429 and a Retry-After header.
+/// Clients are identified by their bearer token if the server knows it, otherwise by IP
+/// address
+pub async fn limit<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    match limiter.check(&limiter.client_key(&request, &remote)) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let retry_secs = retry_after.as_secs_f64().ceil() as u64;
+            tracing::error!(
+                "Rate limited {} request from {} for {retry_secs}s",
+                limiter.name,
+                remote.ip()
+            );
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_secs.max(1).to_string())],
+                "Too many requests, try again later",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let tokens = Arc::new(HashSet::from(["s3cr3t".to_string()]));
+    let limiter = RateLimiter::new("create", 2, tokens);
+    assert!(limiter.check("ip:127.0.0.1").is_ok());
+    assert!(limiter.check("ip:127.0.0.1").is_ok());
+
+    let retry_after = limiter.check("ip:127.0.0.1").unwrap_err();
+    assert!(retry_after > Duration::from_secs(25) && retry_after <= Duration::from_secs(30));
+    assert!(limiter.check("ip:127.0.0.2").is_ok());
+
+    // Only tokens the server knows get a budget of their own
+    let remote = SocketAddr::from(([127, 0, 0, 1], 4000));
+    let request = |token: &str| {
+        Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap()
+    };
+    assert_eq!(limiter.client_key(&request("s3cr3t"), &remote), "token:s3cr3t");
+    assert_eq!(limiter.client_key(&request("made-up"), &remote), "ip:127.0.0.1");
+
+    // Clients past the cap push out the least recently seen ones
+    for i in 0..MAX_CLIENTS + 10 {
+        assert!(limiter.check(&format!("ip:10.0.{}.{}", i / 256, i % 256)).is_ok());
+    }
+    assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_CLIENTS);
+    assert!(limiter.check("ip:10.0.0.0").is_ok());
+
+    let unlimited = RateLimiter::new("redirect", 0, Arc::default());
+    for _ in 0..100 {
+        assert!(unlimited.check("ip:127.0.0.1").is_ok());
+    }
+}
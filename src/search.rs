@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+
+use crate::db::Shortcut;
+
+/// Shortest beginning of a word that is indexed, so "kube" finds "kubernetes"
+const MIN_PREFIX_LEN: usize = 3;
+
+/// Longest word indexed, longer ones are cut off
+const MAX_WORD_LEN: usize = 32;
+
+/// Most terms indexed for one shortcut, each of which takes a storage update. Words past
+/// these, starting with the description, aren't found
+const MAX_TERMS: usize = 200;
+
+/// Most words a search query can have, each of which is looked up in the search index
+pub const MAX_QUERY_TERMS: usize = 8;
+
+/// Results returned when no limit is given, and the most that can be asked for
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+/// Lowercase words of the text, split on anything that isn't a letter or digit
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().chars().take(MAX_WORD_LEN).collect())
+}
+
+/// Words of a link, leaving out its scheme and www
+fn link_words(link: &str) -> impl Iterator<Item = String> + '_ {
+    let link = link.split_once("://").map_or(link, |(_, rest)| rest);
+    words(link.strip_prefix("www.").unwrap_or(link))
+}
+
+/// Fields searched, most important first, with the weight of a match in each and whether
+/// beginnings of its words match. A description can be long, so only whole words of it do
+fn fields(shortcut: &Shortcut) -> [(Vec<String>, u32, bool); 5] {
+    [
+        (words(&shortcut.hash).collect(), 5, true),
+        (shortcut.tags.iter().flat_map(|tag| words(tag)).collect(), 4, true),
+        (shortcut.title.iter().flat_map(|title| words(title)).collect(), 3, true),
+        (link_words(&shortcut.link).collect(), 2, true),
+        (shortcut.description.iter().flat_map(|d| words(d)).collect(), 1, false),
+    ]
+}
+
+/// Terms stored with a shortcut for the storage layer to match searches against: every
+/// searched word and the beginnings of those which match by them, up to [`MAX_TERMS`]
+pub fn terms(shortcut: &Shortcut) -> Vec<String> {
+    let mut terms = BTreeSet::new();
+    for (words, _, prefixes) in fields(shortcut) {
+        for word in words {
+            let chars = word.chars().collect::<Vec<_>>();
+            let shortest = match prefixes {
+                true => MIN_PREFIX_LEN.min(chars.len()),
+                false => chars.len(),
+            };
+            for len in (shortest..=chars.len()).rev() {
+                if terms.len() == MAX_TERMS {
+                    return terms.into_iter().collect();
+                }
+                terms.insert(chars[..len].iter().collect::<String>());
+            }
+        }
+    }
+    terms.into_iter().collect()
+}
+
+/// Terms a search query looks for, each of which a shortcut must match
+pub fn query_terms(query: &str) -> Vec<String> {
+    let terms = words(query).collect::<BTreeSet<_>>();
+    terms.into_iter().collect()
+}
+
+/// Ranks how well the shortcut matches every term, or None if a term isn't found. Whole
+/// words count double beginnings of words, and the hash matters most, then tags, title,
+/// link and description
+pub fn score(shortcut: &Shortcut, terms: &[String]) -> Option<u32> {
+    let fields = fields(shortcut);
+    let mut score = 0;
+
+    for term in terms {
+        let prefix = term.chars().count() >= MIN_PREFIX_LEN;
+        let best = fields
+            .iter()
+            .map(|(words, weight, prefixes)| {
+                let starts = prefix && *prefixes && words.iter().any(|w| w.starts_with(term.as_str()));
+                match (words.contains(term), starts) {
+                    (true, _) => weight * 2,
+                    (false, true) => *weight,
+                    (false, false) => 0,
+                }
+            })
+            .max()
+            .unwrap_or(0);
+
+        if best == 0 {
+            return None;
+        }
+        score += best;
+    }
+
+    Some(score)
+}
+
+#[test]
+fn test_search() {
+    let shortcut = Shortcut {
+        link: "https://www.kubernetes.io/docs/home/".to_string(),
+        hash: "k8s-docs".to_string(),
+        title: Some("Kubernetes Documentation".to_string()),
+        description: Some("Where to start with the cluster".to_string()),
+        tags: vec!["infra".to_string()],
+        ..Shortcut::default()
+    };
+
+    let terms = terms(&shortcut);
+    for term in ["k8s", "docs", "doc", "kubernetes", "kub", "infra", "cluster", "io"] {
+        assert!(terms.contains(&term.to_string()), "{term}");
+    }
+    for term in ["https", "www", "ku", "k8s-docs", "clus"] {
+        assert!(!terms.contains(&term.to_string()), "{term}");
+    }
+
+    assert_eq!(query_terms("  Kube, DOCS!  docs"), ["docs", "kube"]);
+    assert!(query_terms(" -- ").is_empty());
+
+    // Every term must match somewhere
+    assert!(score(&shortcut, &query_terms("kube cluster")).is_some());
+    assert!(score(&shortcut, &query_terms("kube gitlab")).is_none());
+    assert!(score(&shortcut, &query_terms("ku")).is_none());
+    assert!(score(&shortcut, &query_terms("clus")).is_none());
+
+    // Long descriptions don't take a storage update for every word
+    let long = Shortcut {
+        description: Some((0..1000).map(|i| format!("word{i}")).collect::<Vec<_>>().join(" ")),
+        ..shortcut.clone()
+    };
+    let long_terms = crate::search::terms(&long);
+    assert_eq!(long_terms.len(), MAX_TERMS);
+    assert!(long_terms.contains(&"kubernetes".to_string()));
+
+    // Whole words beat beginnings, and the hash beats the description
+    let whole = score(&shortcut, &query_terms("docs")).unwrap();
+    let start = score(&shortcut, &query_terms("doc")).unwrap();
+    let description = score(&shortcut, &query_terms("cluster")).unwrap();
+    assert!(whole > start && start > description);
+}
@@ -0,0 +1,345 @@
+use std::{
+    env, fs, io,
+    net::TcpListener,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    args::{ClapArgs, InstallServiceCommand},
+    listener::{self, ListenAddr, Listener},
+};
+
+const SERVICE_NAME: &str = "cli_shortener";
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening sockets passed by systemd, if the server was socket activated
+pub fn activated_listeners() -> Vec<Listener> {
+    let activated = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let fds = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+
+    if !activated || fds < 1 {
+        return Vec::new();
+    }
+    // Only taken once, so later lookups don't wrap descriptors that are no longer ours
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds)
+        .filter_map(|fd| match from_fd(fd) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                tracing::error!("Cannot use socket {fd} passed by systemd: {e:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn from_fd(fd: i32) -> io::Result<Listener> {
+    use std::os::unix::{
+        io::{FromRawFd, IntoRawFd},
+        net::UnixListener,
+    };
+
+    // systemd hands over ownership of the descriptor. It is a TCP socket if it has an
+    // IP address, otherwise a Unix domain socket
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    let listener = match listener.local_addr() {
+        Ok(_) => Listener::Tcp(listener),
+        Err(_) => Listener::Unix(unsafe { UnixListener::from_raw_fd(listener.into_raw_fd()) }),
+    };
+
+    match &listener {
+        Listener::Tcp(l) => l.set_nonblocking(true)?,
+        Listener::Unix(l) => l.set_nonblocking(true)?,
+    }
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn from_fd(_fd: i32) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket activation is only supported on unix",
+    ))
+}
+
+/// Writes the systemd units into the user or system unit directory, returning their paths
+pub fn install(args: &ClapArgs, command: &InstallServiceCommand) -> io::Result<Vec<PathBuf>> {
+    let unit_dir = match command.system {
+        true => PathBuf::from("/etc/systemd/system"),
+        false => dirs::config_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?
+            .join("systemd/user"),
+    };
+    fs::create_dir_all(&unit_dir)?;
+
+    let mut written = Vec::new();
+    for (name, contents) in unit_files(args, command, &env::current_exe()?, &env::current_dir()?) {
+        let path = unit_dir.join(name);
+        fs::write(&path, contents)?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Generates the service unit, plus a socket unit if socket activation is wanted
+pub fn unit_files(
+    args: &ClapArgs,
+    command: &InstallServiceCommand,
+    exe: &Path,
+    working_dir: &Path,
+) -> Vec<(String, String)> {
+    let wanted_by = match command.system {
+        true => "multi-user.target",
+        false => "default.target",
+    };
+    let socket_deps = match command.socket {
+        true => format!("Requires={SERVICE_NAME}.socket\nAfter={SERVICE_NAME}.socket\n"),
+        false => "".to_string(),
+    };
+    // System units would run as root otherwise
+    let user = match (command.system, service_user(command)) {
+        (false, _) => "".to_string(),
+        (true, Some(user)) => format!("User={user}\n"),
+        (true, None) => "DynamicUser=yes\n".to_string(),
+    };
+    let exec_start = std::iter::once(exe.display().to_string())
+        .chain(server_args(args))
+        .map(|arg| exec_arg(&arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let service = format!(
+        "[Unit]
+Description=CLI Shortener links server
+After=network-online.target
+Wants=network-online.target
+{socket_deps}
+[Service]
+Type=simple
+{user}WorkingDirectory={working_dir}
+EnvironmentFile=-{env_file}
+ExecStart={exec_start}
+Restart=on-failure
+TimeoutStopSec={stop_timeout}
+
+[Install]
+WantedBy={wanted_by}
+",
+        working_dir = working_dir.display(),
+        env_file = working_dir.join(".env").display(),
+        stop_timeout = args.drain_timeout + 5,
+    );
+
+    let mut units = vec![(format!("{SERVICE_NAME}.service"), service)];
+
+    if command.socket {
+        let socket = format!(
+            "[Unit]
+Description=CLI Shortener links server socket
+
+[Socket]
+{listen_streams}
+
+[Install]
+WantedBy=sockets.target
+",
+            listen_streams = listener::listen_addrs(args)
+                .iter()
+                .map(|addr| match addr {
+                    ListenAddr::Tcp(addr) => format!("ListenStream={addr}"),
+                    ListenAddr::Unix(path) => format!("ListenStream={}", path.display()),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+        units.push((format!("{SERVICE_NAME}.socket"), socket));
+    }
+
+    units
+}
+
+/// User a system unit runs the server as: the one given, or else the one running sudo
+fn service_user(command: &InstallServiceCommand) -> Option<String> {
+    command
+        .user
+        .clone()
+        .or_else(|| env::var("SUDO_USER").ok().filter(|user| !user.is_empty() && user != "root"))
+}
+
+/// Writes an argument for ExecStart. % and $ are doubled so systemd doesn't expand them,
+/// and arguments with spaces, quotes or backslashes are quoted with C-style escapes
+fn exec_arg(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c.is_control() || "\"'\\".contains(c)) {
+        return arg;
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Server options from the current invocation written out explicitly, one argument per
+/// item, for ExecStart and for starting the server in the background
+pub fn server_args(args: &ClapArgs) -> Vec<String> {
+    let value_name = |v: &dyn Fn() -> Option<clap::builder::PossibleValue>| {
+        v().map(|p| p.get_name().to_string()).unwrap_or_default()
+    };
+
+    let mut options = vec![
+        ("--host", args.host.to_string()),
+        ("--port", args.port.to_string()),
+        ("--log-format", value_name(&|| args.log_format.to_possible_value())),
+        ("--drain-timeout", args.drain_timeout.to_string()),
+        ("--create-limit", args.create_limit.to_string()),
+        ("--delete-limit", args.delete_limit.to_string()),
+        ("--redirect-limit", args.redirect_limit.to_string()),
+        ("--cache-size", args.cache_size.to_string()),
+        ("--cache-ttl", args.cache_ttl.to_string()),
+        ("--trash-days", args.trash_days.to_string()),
+    ];
+
+    for addr in &args.listen {
+        options.push(("--listen", addr.to_string()));
+    }
+    if let Some(admin_socket) = &args.admin_socket {
+        options.push(("--admin-socket", admin_socket.display().to_string()));
+        options.push(("--admin-socket-mode", format!("{:o}", args.admin_socket_mode)));
+    }
+    if !args.path.is_empty() {
+        options.push(("--path", args.path.to_string()));
+    }
+    if let Some(public_url) = &args.public_url {
+        options.push(("--public-url", public_url.to_string()));
+    }
+    if let Some(tokens) = &args.rate_limit_tokens {
+        options.push(("--rate-limit-tokens", tokens.display().to_string()));
+    }
+    if let Some(access_log) = &args.access_log {
+        options.push(("--access-log", access_log.to_string()));
+        options.push((
+            "--access-log-format",
+            value_name(&|| args.access_log_format.to_possible_value()),
+        ));
+        options.push(("--access-log-max-size", args.access_log_max_size.to_string()));
+    }
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &args.otlp_endpoint {
+        options.push(("--otlp-endpoint", endpoint.to_string()));
+    }
+
+    let mut server_args = options
+        .into_iter()
+        .flat_map(|(name, value)| [name.to_string(), value])
+        .collect::<Vec<_>>();
+    if args.strict_port {
+        server_args.push("--strict-port".to_string());
+    }
+    if args.verbose {
+        server_args.push("--verbose".to_string());
+    }
+
+    server_args.push("start".to_string());
+    server_args
+}
+
+#[test]
+fn test_unit_files() {
+    use clap::Parser;
+
+    let args = ClapArgs::parse_from([
+        "cli_shortener",
+        "--port",
+        "9000",
+        "--path",
+        "go",
+        "--listen",
+        "[::]:9000",
+        "--listen",
+        "unix:/run/links.sock",
+        "install-service",
+        "--socket",
+    ]);
+    let command = match &args.entity_type {
+        crate::args::EntityType::InstallService(command) => command,
+        _ => panic!("Expected install-service command"),
+    };
+
+    let units = unit_files(&args, command, Path::new("/usr/bin/cli_shortener"), Path::new("/srv/links"));
+    assert_eq!(units.len(), 2);
+
+    let (service_name, service) = &units[0];
+    assert_eq!(service_name, "cli_shortener.service");
+    assert!(service.contains("Requires=cli_shortener.socket"));
+    assert!(service.contains("EnvironmentFile=-/srv/links/.env"));
+    assert!(service.contains("ExecStart=/usr/bin/cli_shortener --host 127.0.0.1 --port 9000"));
+    assert!(service.contains("--listen [::]:9000 --listen unix:/run/links.sock --path go start\n"));
+    assert!(service.contains("WantedBy=default.target"));
+
+    assert!(!service.contains("User="));
+
+    let (socket_name, socket) = &units[1];
+    assert_eq!(socket_name, "cli_shortener.socket");
+    assert!(socket.contains("ListenStream=[::]:9000\nListenStream=/run/links.sock\n"));
+
+    // Arguments are quoted and escaped for systemd, and system units don't run as root
+    let args = ClapArgs::parse_from([
+        "cli_shortener",
+        "--access-log",
+        "/var/log/links 100%.log",
+        "install-service",
+        "--system",
+        "--user",
+        "links",
+    ]);
+    let command = match &args.entity_type {
+        crate::args::EntityType::InstallService(command) => command,
+        _ => panic!("Expected install-service command"),
+    };
+    let units = unit_files(&args, command, Path::new("/opt/my links/cli_shortener"), Path::new("/srv/links"));
+    let (_, service) = &units[0];
+    assert!(service.contains("User=links\n"));
+    assert!(service.contains("ExecStart=\"/opt/my links/cli_shortener\" --host"));
+    assert!(service.contains("--access-log \"/var/log/links 100%%.log\""));
+    assert!(service.contains("WantedBy=multi-user.target"));
+
+    assert_eq!(exec_arg("$HOME"), "$$HOME");
+    assert_eq!(exec_arg(r#"say "hi"\"#), r#""say \"hi\"\\""#);
+    assert_eq!(exec_arg(""), "\"\"");
+}
+
+#[test]
+fn test_server_args() {
+    use clap::Parser;
+
+    // Options are written out in full, so values and combined flags can't be mistaken
+    let args = ClapArgs::parse_from(["cli_shortener", "--path", "restart", "-v", "--port", "9000", "start", "-d"]);
+    let server_args = server_args(&args);
+    assert!(server_args.windows(2).any(|w| w == ["--path", "restart"]));
+    assert!(server_args.windows(2).any(|w| w == ["--port", "9000"]));
+    assert!(server_args.contains(&"--verbose".to_string()));
+    assert!(!server_args.iter().any(|arg| arg == "-d" || arg == "--detach"));
+    assert_eq!(server_args.last().map(String::as_str), Some("start"));
+}
@@ -0,0 +1,473 @@
+use std::collections::HashSet;
+
+use aws_sdk_dynamodb::Client;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+use crate::{
+    audit,
+    db::{self, AuditAction, AuditEvent, DbError, Shortcut},
+    search,
+    utils::{self, CreateLink, DeleteFilter, EditLink, UrlError},
+};
+
+/// Errors from managing shortcuts, whether through the server or directly from the CLI
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum ShortcutError {
+    #[error("link is not a valid URL")]
+    LinkError(#[from] UrlError),
+
+    #[error("password could not be hashed")]
+    PasswordError(String),
+
+    #[error("alias must be letters, digits, - and _, and not a reserved name")]
+    AliasError(String),
+
+    #[error("alias is already taken")]
+    TakenError(String),
+
+    #[error("invalid metadata: {0}")]
+    MetadataError(String),
+
+    #[error("shortcut does not exist")]
+    NotFoundError(String),
+
+    #[error("storage request failed")]
+    StorageError(#[from] DbError),
+}
+
+/// Links accepted in one batch request, larger batches are sent in chunks by the CLI
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Generated hashes tried before giving up when they are already taken
+const MAX_HASH_ATTEMPTS: usize = 5;
+
+/// Limits on the metadata of a shortcut, in characters
+const MAX_TITLE_LEN: usize = 200;
+const MAX_DESCRIPTION_LEN: usize = 2000;
+const MAX_TAG_LEN: usize = 50;
+const MAX_TAGS: usize = 20;
+
+/// Title, description and tags of a shortcut
+type Metadata = (Option<String>, Option<String>, Vec<String>);
+
+/// Validates the link and stores it under its alias or a newly generated hash
+pub async fn create_shortcut(
+    client: &Client,
+    table_name: &str,
+    create_link: &CreateLink,
+    actor: &str,
+) -> Result<Shortcut, ShortcutError> {
+    let link = utils::normalize_link(&create_link.link, create_link.strip_tracking)?;
+
+    let password_hash = match create_link.password.as_deref().map(utils::hash_password) {
+        None => None,
+        Some(Ok(password_hash)) => Some(password_hash),
+        Some(Err(e)) => return Err(ShortcutError::PasswordError(e.to_string())),
+    };
+
+    if let Some(alias) = &create_link.alias {
+        if !utils::is_valid_alias(alias) {
+            return Err(ShortcutError::AliasError(alias.to_string()));
+        }
+    }
+
+    let (title, description, tags) = metadata(
+        create_link.title.as_deref(),
+        create_link.description.as_deref(),
+        &create_link.tags,
+    )?;
+
+    let now = now();
+    let mut shortcut = Shortcut {
+        link,
+        hash: String::new(),
+        prefix: create_link.prefix,
+        password_hash,
+        created_at: Some(now),
+        updated_at: Some(now),
+        created_by: Some(actor.to_string()),
+        deleted_at: None,
+        title,
+        description,
+        tags,
+    };
+
+    // Hashes stay taken after being deleted, so a generated one may have to be tried again
+    for _ in 0..MAX_HASH_ATTEMPTS {
+        shortcut.hash = create_link.alias.clone().unwrap_or_else(utils::gen_hash);
+
+        match db::add_shortcut(client, table_name, &shortcut).await {
+            Ok(_) => {
+                let link = Some(shortcut.link.clone());
+                log(client, table_name, &shortcut.hash, AuditAction::Create, actor, link).await;
+                index(client, table_name, &shortcut.hash, &search::terms(&shortcut), &[]).await;
+                return Ok(shortcut);
+            }
+            Err(DbError::DuplicationError()) if create_link.alias.is_some() => {
+                return Err(ShortcutError::TakenError(shortcut.hash))
+            }
+            Err(DbError::DuplicationError()) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(DbError::DuplicationError().into())
+}
+
+/// Creates a shortcut for every link in turn, so one failing does not stop the rest
+pub async fn create_shortcuts(
+    client: &Client,
+    table_name: &str,
+    create_links: &[CreateLink],
+    actor: &str,
+) -> Vec<Result<Shortcut, ShortcutError>> {
+    let mut created = Vec::with_capacity(create_links.len());
+    for create_link in create_links {
+        created.push(create_shortcut(client, table_name, create_link, actor).await);
+    }
+    created
+}
+
+/// Every stored shortcut, including deleted and purged ones
+async fn all_shortcuts(client: &Client, table_name: &str) -> Result<Vec<Shortcut>, ShortcutError> {
+    let items = db::get_all_shortcuts(client, table_name).await?;
+    Ok(items.iter().filter_map(Shortcut::from_item).collect())
+}
+
+pub async fn list_shortcuts(client: &Client, table_name: &str) -> Result<Vec<Shortcut>, ShortcutError> {
+    let shortcuts = all_shortcuts(client, table_name).await?;
+    Ok(shortcuts.into_iter().filter(|s| !s.is_deleted()).collect())
+}
+
+/// Shortcuts not in the trash matching every term of the query, best matches first
+pub async fn search_shortcuts(
+    client: &Client,
+    table_name: &str,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<Shortcut>, ShortcutError> {
+    let terms = search::query_terms(query);
+    let items = db::search_shortcuts(client, table_name, &terms).await?;
+
+    let mut ranked = items
+        .iter()
+        .filter_map(Shortcut::from_item)
+        .filter(|s| !s.is_deleted())
+        .filter_map(|s| search::score(&s, &terms).map(|score| (score, s)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.hash.cmp(&b.hash)));
+
+    Ok(ranked.into_iter().take(limit).map(|(_, s)| s).collect())
+}
+
+/// Deleted shortcuts which can still be restored
+pub async fn list_trash(client: &Client, table_name: &str) -> Result<Vec<Shortcut>, ShortcutError> {
+    let shortcuts = all_shortcuts(client, table_name).await?;
+    Ok(shortcuts
+        .into_iter()
+        .filter(|s| s.is_deleted() && !s.is_purged())
+        .collect())
+}
+
+/// Changes the title, description or tags of the shortcut with the given hash, which must
+/// exist and not be deleted
+pub async fn edit_shortcut(
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+    edit_link: &EditLink,
+    actor: &str,
+) -> Result<Shortcut, ShortcutError> {
+    let shortcut = match db::get_shortcut(client, table_name, hash).await {
+        Ok(shortcut) if !shortcut.is_deleted() => shortcut,
+        Ok(_) | Err(DbError::NotFoundError(_)) => return Err(ShortcutError::NotFoundError(hash.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+
+    let (title, description, tags) = metadata(
+        edit_link.title.as_deref().or(shortcut.title.as_deref()),
+        edit_link.description.as_deref().or(shortcut.description.as_deref()),
+        edit_link.tags.as_ref().unwrap_or(&shortcut.tags),
+    )?;
+    let old_terms = search::terms(&shortcut);
+    let changed = [
+        ("title", title != shortcut.title),
+        ("description", description != shortcut.description),
+        ("tags", tags != shortcut.tags),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect::<Vec<_>>();
+    let shortcut = Shortcut {
+        title,
+        description,
+        tags,
+        updated_at: Some(now()),
+        ..shortcut
+    };
+
+    db::update_metadata(client, table_name, &shortcut).await?;
+    let detail = Some(changed.join(", ")).filter(|d| !d.is_empty());
+    log(client, table_name, hash, AuditAction::Edit, actor, detail).await;
+
+    let new_terms = search::terms(&shortcut);
+    let added = new_terms.iter().filter(|t| !old_terms.contains(t)).cloned().collect::<Vec<_>>();
+    let removed = old_terms.iter().filter(|t| !new_terms.contains(t)).cloned().collect::<Vec<_>>();
+    index(client, table_name, hash, &added, &removed).await;
+    Ok(shortcut)
+}
+
+/// Checks the title, description and tags fit their limits, returning them trimmed with
+/// empty ones left out and tags normalized
+fn metadata(
+    title: Option<&str>,
+    description: Option<&str>,
+    tags: &[String],
+) -> Result<Metadata, ShortcutError> {
+    let text = |text: Option<&str>, max_len: usize, name: &str| {
+        match text.map(str::trim).filter(|t| !t.is_empty()) {
+            Some(text) if text.chars().count() > max_len => Err(ShortcutError::MetadataError(format!(
+                "{name} is longer than {max_len} characters"
+            ))),
+            text => Ok(text.map(str::to_string)),
+        }
+    };
+    let title = text(title, MAX_TITLE_LEN, "title")?;
+    let description = text(description, MAX_DESCRIPTION_LEN, "description")?;
+
+    let tags = utils::normalize_tags(tags.iter().map(String::as_str));
+    if tags.len() > MAX_TAGS {
+        return Err(ShortcutError::MetadataError(format!("more than {MAX_TAGS} tags")));
+    }
+    if let Some(tag) = tags
+        .iter()
+        .find(|tag| tag.chars().count() > MAX_TAG_LEN || tag.contains(|c: char| c == ',' || c.is_whitespace()))
+    {
+        return Err(ShortcutError::MetadataError(format!("invalid tag {tag}")));
+    }
+
+    Ok((title, description, tags))
+}
+
+/// Moves the shortcut with the given hash to the trash, which must exist and not be deleted
+pub async fn delete_shortcut(client: &Client, table_name: &str, hash: &str, actor: &str) -> Result<(), ShortcutError> {
+    match db::get_shortcut(client, table_name, hash).await {
+        Ok(shortcut) if !shortcut.is_deleted() => {}
+        Ok(_) | Err(DbError::NotFoundError(_)) => return Err(ShortcutError::NotFoundError(hash.to_string())),
+        Err(e) => return Err(e.into()),
+    }
+
+    let now = now();
+    match db::set_deleted(client, table_name, hash, Some(now), now).await {
+        Ok(()) => {}
+        Err(DbError::NotFoundError(_)) => return Err(ShortcutError::NotFoundError(hash.to_string())),
+        Err(e) => return Err(e.into()),
+    }
+    log(client, table_name, hash, AuditAction::Delete, actor, None).await;
+    Ok(())
+}
+
+/// Moves every shortcut the filter matches to the trash, returning them
+pub async fn delete_shortcuts(
+    client: &Client,
+    table_name: &str,
+    filter: &DeleteFilter,
+    actor: &str,
+) -> Result<Vec<Shortcut>, ShortcutError> {
+    let now = now();
+    let matched = list_shortcuts(client, table_name)
+        .await?
+        .into_iter()
+        .filter(|shortcut| matches(filter, shortcut, now))
+        .collect::<Vec<_>>();
+
+    let hashes = matched.iter().map(|s| s.hash.clone()).collect::<Vec<_>>();
+    let deleted_hashes = db::delete_shortcuts(client, table_name, &hashes, now)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut deleted = Vec::with_capacity(deleted_hashes.len());
+    for shortcut in matched.into_iter().filter(|s| deleted_hashes.contains(&s.hash)) {
+        log(client, table_name, &shortcut.hash, AuditAction::Delete, actor, None).await;
+        deleted.push(Shortcut {
+            updated_at: Some(now),
+            deleted_at: Some(now),
+            ..shortcut
+        });
+    }
+    Ok(deleted)
+}
+
+/// Takes the shortcut with the given hash back out of the trash
+pub async fn restore_shortcut(
+    client: &Client,
+    table_name: &str,
+    hash: &str,
+    actor: &str,
+) -> Result<Shortcut, ShortcutError> {
+    let shortcut = match db::get_shortcut(client, table_name, hash).await {
+        Ok(shortcut) if shortcut.is_deleted() && !shortcut.is_purged() => shortcut,
+        Ok(_) | Err(DbError::NotFoundError(_)) => return Err(ShortcutError::NotFoundError(hash.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+
+    let now = now();
+    db::set_deleted(client, table_name, hash, None, now).await?;
+    log(client, table_name, hash, AuditAction::Restore, actor, None).await;
+    Ok(Shortcut {
+        updated_at: Some(now),
+        deleted_at: None,
+        ..shortcut
+    })
+}
+
+/// Purges shortcuts which have been in the trash for longer than the retention period,
+/// returning how many were purged
+pub async fn purge_trash(client: &Client, table_name: &str, retention_secs: u64) -> Result<usize, ShortcutError> {
+    let now = now();
+    let expired = list_trash(client, table_name)
+        .await?
+        .into_iter()
+        .filter(|s| s.deleted_at.is_some_and(|deleted_at| now.saturating_sub(deleted_at) >= retention_secs))
+        .collect::<Vec<_>>();
+
+    for shortcut in &expired {
+        db::purge_shortcut(client, table_name, &shortcut.hash).await?;
+        log(client, table_name, &shortcut.hash, AuditAction::Purge, audit::SERVER_ACTOR, None).await;
+        index(client, table_name, &shortcut.hash, &[], &search::terms(shortcut)).await;
+    }
+    Ok(expired.len())
+}
+
+/// Adds shortcuts stored before the search index existed to it, returning how many were
+/// added
+pub async fn index_shortcuts(client: &Client, table_name: &str) -> Result<usize, ShortcutError> {
+    let items = db::get_unindexed_shortcuts(client, table_name).await?;
+    let unindexed = items.iter().filter_map(Shortcut::from_item).collect::<Vec<_>>();
+
+    for shortcut in &unindexed {
+        db::index_terms(client, table_name, &shortcut.hash, &search::terms(shortcut), &[]).await?;
+        db::set_search_terms(client, table_name, shortcut).await?;
+    }
+    Ok(unindexed.len())
+}
+
+/// The audit log of the shortcut with the given hash, or of every shortcut, newest first
+pub async fn audit_log(
+    client: &Client,
+    table_name: &str,
+    hash: Option<&str>,
+) -> Result<Vec<AuditEvent>, ShortcutError> {
+    let mut events = match hash {
+        Some(hash) => db::get_audit(client, table_name, hash).await?,
+        None => db::get_all_audits(client, table_name).await?,
+    };
+    // Each log is stored oldest first, so reversing keeps changes within a second in order
+    events.reverse();
+    events.sort_by_key(|e| std::cmp::Reverse(e.at));
+    Ok(events)
+}
+
+/// Records a change in the audit log. The change itself has already been made, so a
+/// failure is logged and counted in the db_errors metric as append_audit rather than
+/// failing the request
+async fn log(client: &Client, table_name: &str, hash: &str, action: AuditAction, actor: &str, detail: Option<String>) {
+    let event = AuditEvent {
+        hash: hash.to_string(),
+        at: now(),
+        action,
+        actor: actor.to_string(),
+        detail,
+    };
+
+    if let Err(e) = db::append_audit(client, table_name, &event).await {
+        tracing::error!("Could not log {} of shortcut with {hash} by {actor}: {e:?}", action.as_str());
+    }
+}
+
+/// Updates the search index after a change, logging failures like [`log`]
+async fn index(client: &Client, table_name: &str, hash: &str, added: &[String], removed: &[String]) {
+    if let Err(e) = db::index_terms(client, table_name, hash, added, removed).await {
+        tracing::error!("Could not update the search index for shortcut with {hash}: {e:?}");
+    }
+}
+
+/// Checks the shortcut against every part of the filter. Links are compared after
+/// normalizing, and shortcuts without a creation time are never old enough
+fn matches(filter: &DeleteFilter, shortcut: &Shortcut, now: u64) -> bool {
+    let link_matches = filter.link.as_ref().is_none_or(|link| {
+        *link == shortcut.link
+            || utils::normalize_link(link, false).is_ok_and(|link| link == shortcut.link)
+    });
+    let pattern_matches = filter
+        .pattern
+        .as_ref()
+        .is_none_or(|pattern| utils::glob_match(pattern, &shortcut.hash));
+    let old_enough = filter.older_than_secs.is_none_or(|secs| {
+        shortcut.created_at.is_some_and(|created_at| now.saturating_sub(created_at) >= secs)
+    });
+
+    link_matches && pattern_matches && old_enough
+}
+
+/// Current Unix time in seconds
+fn now() -> u64 {
+    OffsetDateTime::now_utc().unix_timestamp().max(0) as u64
+}
+
+#[test]
+fn test_delete_filter() {
+    let shortcut = Shortcut {
+        link: "https://example.com/releases/1.0".to_string(),
+        hash: "release-1-0".to_string(),
+        created_at: Some(1_000),
+        ..Shortcut::default()
+    };
+    let now = 1_000 + 30 * 24 * 60 * 60;
+
+    assert!(matches(&DeleteFilter::default(), &shortcut, now));
+
+    let by_link = DeleteFilter {
+        link: Some("example.com/releases/1.0".to_string()),
+        ..DeleteFilter::default()
+    };
+    assert!(matches(&by_link, &shortcut, now));
+
+    let by_pattern_and_age = DeleteFilter {
+        pattern: Some("release-*".to_string()),
+        older_than_secs: Some(30 * 24 * 60 * 60),
+        ..DeleteFilter::default()
+    };
+    assert!(matches(&by_pattern_and_age, &shortcut, now));
+    assert!(!matches(&by_pattern_and_age, &shortcut, now - 1));
+
+    let unknown_age = Shortcut {
+        created_at: None,
+        ..shortcut.clone()
+    };
+    assert!(!matches(&by_pattern_and_age, &unknown_age, now));
+
+    let other_link = DeleteFilter {
+        link: Some("https://example.com/".to_string()),
+        ..DeleteFilter::default()
+    };
+    assert!(!matches(&other_link, &shortcut, now));
+}
+
+#[test]
+fn test_metadata() {
+    let tags = vec!["Infra".to_string(), " web ".to_string(), "infra".to_string()];
+    let (title, description, tags) = metadata(Some(" Search "), Some(""), &tags).unwrap();
+    assert_eq!(title.as_deref(), Some("Search"));
+    assert_eq!(description, None);
+    assert_eq!(tags, ["infra", "web"]);
+
+    assert!(metadata(Some(&"a".repeat(MAX_TITLE_LEN + 1)), None, &[]).is_err());
+    assert!(metadata(None, None, &["two words".to_string()]).is_err());
+    assert!(metadata(None, None, &["a,b".to_string()]).is_err());
+    let too_many = (0..=MAX_TAGS).map(|i| format!("tag-{i}")).collect::<Vec<_>>();
+    assert!(metadata(None, None, &too_many).is_err());
+}
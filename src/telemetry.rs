@@ -0,0 +1,141 @@
+use std::{
+    io::{self, IsTerminal},
+    time::Duration,
+};
+
+use axum::{
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use tracing::{level_filters::LevelFilter, Span};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+use crate::args::{ClapArgs, LogFormat};
+
+/// Header carrying the id given to each request
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Sets up logging in the chosen format, exporting spans over OTLP if an endpoint is given
+pub fn init_tracing(args: &ClapArgs) {
+    let level = match args.verbose {
+        true => LevelFilter::DEBUG,
+        false => LevelFilter::INFO,
+    };
+
+    let fmt_layer = match args.log_format {
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_ansi(io::stdout().is_terminal())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .boxed(),
+    };
+
+    let registry = tracing_subscriber::registry().with(level).with(fmt_layer);
+
+    #[cfg(feature = "otlp")]
+    {
+        let otlp_layer = match &args.otlp_endpoint {
+            None => None,
+            Some(endpoint) => match otlp_tracer(endpoint) {
+                Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+                Err(e) => {
+                    eprintln!("Could not set up OTLP export to {endpoint}: {e:?}");
+                    None
+                }
+            },
+        };
+        registry.with(otlp_layer).init();
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    registry.init();
+}
+
+/// Flushes any spans still waiting to be exported
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_tracer(
+    endpoint: &str,
+) -> Result<opentelemetry::sdk::trace::Tracer, opentelemetry::trace::TraceError> {
+    use opentelemetry::{sdk, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/'))),
+        )
+        .with_trace_config(sdk::trace::config().with_resource(sdk::Resource::new(vec![
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ])))
+        .install_batch(opentelemetry::runtime::Tokio)
+}
+
+/// Span wrapping every request with its id, method, route, status and latency
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        request_id,
+        method = %request.method(),
+        route,
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    )
+}
+
+/// Records the status and latency on the request span once the response is ready
+pub fn record_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    tracing::info!("Finished request");
+}
+
+#[cfg(feature = "otlp")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_otlp_export() {
+    use opentelemetry::trace::Tracer;
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+    let collector = axum::Router::new().fallback(move |uri: axum::http::Uri| {
+        let sender = sender.clone();
+        async move {
+            sender.send(uri.path().to_string()).ok();
+            axum::http::StatusCode::OK
+        }
+    });
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(collector.into_make_service()));
+
+    otlp_tracer(&endpoint).unwrap();
+    opentelemetry::global::tracer("test").in_span("test-span", |_| {});
+    tokio::task::spawn_blocking(shutdown_tracing).await.unwrap();
+
+    let path = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .unwrap();
+    assert_eq!(path.as_deref(), Some("/v1/traces"));
+}
@@ -1,17 +1,376 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use url::Url;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum UrlError {
+    #[error("link could not be parsed")]
+    ParseError(String),
+
+    #[error("link uses an unsupported scheme")]
+    SchemeError(String),
+
+    #[error("link does not have a host")]
+    HostError(),
+
+    #[error("link template is invalid")]
+    TemplateError(String),
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct CreateLink {
     pub link: String,
+
+    /// Remove known tracking parameters (utm_*, fbclid, ...) from the link
+    #[serde(default)]
+    pub strip_tracking: bool,
+
+    /// Forward extra path segments and query parameters onto the link
+    #[serde(default)]
+    pub prefix: bool,
+
+    /// Password required before the shortcut redirects
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Hash to use instead of a generated one
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    #[serde(default)]
+    pub title: Option<String>,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Changes to a shortcut's metadata, leaving out what stays the same. Empty values
+/// clear the title, description or tags
+#[derive(Default, Deserialize, Serialize)]
+pub struct EditLink {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Which shortcuts are listed, all of them if nothing is set
+#[derive(Default, Deserialize, Serialize)]
+pub struct ListFilter {
+    /// Comma separated tags the shortcuts must all have
+    pub tag: Option<String>,
+}
+
+impl ListFilter {
+    pub fn tags(&self) -> Vec<String> {
+        normalize_tags(self.tag.iter().flat_map(|tag| tag.split(',')))
+    }
+
+    /// Route of the list endpoint with the filter as its query
+    pub fn route(&self) -> String {
+        match &self.tag {
+            None => "all".to_string(),
+            Some(tag) => {
+                let query = url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("tag", tag)
+                    .finish();
+                format!("all?{query}")
+            }
+        }
+    }
+}
+
+/// Query of the search endpoint, matched against the hash, link, title, tags and description
+#[derive(Deserialize, Serialize)]
+pub struct SearchQuery {
+    pub q: String,
+
+    /// Most results returned, 20 if not given
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    /// Route of the search endpoint with the query
+    pub fn route(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query.append_pair("q", &self.q);
+        if let Some(limit) = self.limit {
+            query.append_pair("limit", &limit.to_string());
+        }
+        format!("search?{}", query.finish())
+    }
+}
+
+/// Whose changes are shown from the audit log, every shortcut's if nothing is set
+#[derive(Default, Deserialize, Serialize)]
+pub struct AuditFilter {
+    pub hash: Option<String>,
+}
+
+impl AuditFilter {
+    /// Route of the audit endpoint with the filter as its query
+    pub fn route(&self) -> String {
+        match &self.hash {
+            None => "audit".to_string(),
+            Some(hash) => {
+                let query = url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("hash", hash)
+                    .finish();
+                format!("audit?{query}")
+            }
+        }
+    }
+}
+
+/// Outcome of creating one link of a batch, with either the new shortcut or the error
+#[derive(Deserialize, Serialize)]
+pub struct BatchResult {
+    pub link: String,
+    pub shortcut: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Which shortcuts a bulk delete removes. Every one of them is only removed when `all` is
+/// set, and a misspelled filter is refused rather than ignored
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteFilter {
+    /// Original link the shortcuts redirect to
+    pub link: Option<String>,
+
+    /// Glob like "release-*" matched against the hashes
+    #[serde(rename = "match")]
+    pub pattern: Option<String>,
+
+    /// Only shortcuts created at least this many seconds ago
+    pub older_than_secs: Option<u64>,
+
+    /// Set to delete every shortcut when no other filter is given
+    #[serde(default)]
+    pub all: bool,
+}
+
+impl DeleteFilter {
+    /// Whether no filter narrows down the shortcuts deleted
+    pub fn is_unfiltered(&self) -> bool {
+        self.link.is_none() && self.pattern.is_none() && self.older_than_secs.is_none()
+    }
+
+    /// Route of the bulk delete endpoint with the filter as its query
+    pub fn route(&self) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        if let Some(link) = &self.link {
+            query.append_pair("link", link);
+        }
+        if let Some(pattern) = &self.pattern {
+            query.append_pair("match", pattern);
+        }
+        if let Some(secs) = self.older_than_secs {
+            query.append_pair("older_than_secs", &secs.to_string());
+        }
+        if self.all {
+            query.append_pair("all", "true");
+        }
+
+        match query.finish() {
+            query if query.is_empty() => "all".to_string(),
+            query => format!("all?{query}"),
+        }
+    }
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct ServerStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    pub backend: String,
+    pub ready: bool,
+    pub link_count: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct UnlockShortcut {
+    pub password: String,
+}
+
+/// Query parameters which only exist to track where a click came from
+const TRACKING_PARAMS: [&str; 9] = [
+    "fbclid", "gclid", "dclid", "msclkid", "mc_cid", "mc_eid", "igshid", "yclid", "_ga",
+];
+
+/// Characters left as-is when substituting values into a templated link
+const TEMPLATE_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 #[derive(askama_axum::Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate {
     pub url: String,
 }
 
+#[derive(askama_axum::Template)]
+#[template(path = "password.html")]
+pub struct PasswordTemplate {
+    pub url: String,
+    pub error: String,
+}
+
+/// Failed password attempts allowed per client and shortcut before being locked out
+const MAX_PASSWORD_ATTEMPTS: u32 = 5;
+const PASSWORD_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Tracks failed password attempts so protected shortcuts can't be brute forced
+#[derive(Default)]
+pub struct FailedAttempts {
+    attempts: Mutex<HashMap<(IpAddr, String), (u32, Instant)>>,
+}
+
+impl FailedAttempts {
+    pub fn is_locked(&self, ip: IpAddr, hash: &str) -> bool {
+        let attempts = self.attempts.lock().unwrap();
+        match attempts.get(&(ip, hash.to_string())) {
+            Some((count, first)) => {
+                *count >= MAX_PASSWORD_ATTEMPTS && first.elapsed() < PASSWORD_LOCKOUT
+            }
+            None => false,
+        }
+    }
+
+    pub fn record_failure(&self, ip: IpAddr, hash: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, (_, first)| first.elapsed() < PASSWORD_LOCKOUT);
+
+        let entry = attempts
+            .entry((ip, hash.to_string()))
+            .or_insert((0, Instant::now()));
+        entry.0 += 1;
+    }
+
+    pub fn clear(&self, ip: IpAddr, hash: &str) {
+        self.attempts.lock().unwrap().remove(&(ip, hash.to_string()));
+    }
+}
+
+/// Hashes the shortcut password with argon2 and a random salt
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Full link to a shortcut on the server, e.g. http://127.0.0.1:8080/go/happy-cat
+pub fn shortcut_url(address: &SocketAddr, path: &str, hash: &str) -> String {
+    public_url(&format!("http://{address}"), path, hash)
+}
+
+/// Link of a shortcut under the address the server is reached at, like https://go.example.com
+pub fn public_url(base_url: &str, path: &str, hash: &str) -> String {
+    match path {
+        "" => format!("{base_url}/{hash}"),
+        path => format!("{base_url}/{path}/{hash}"),
+    }
+}
+
+/// Hash of a shortcut given as a full link or as just the hash, e.g. happy-cat for
+/// http://127.0.0.1:8080/go/happy-cat/
+pub fn shortcut_hash(shortcut: &str) -> &str {
+    let shortcut = shortcut.trim();
+    let shortcut = shortcut.split(['?', '#']).next().unwrap_or_default();
+    shortcut.trim_end_matches('/').rsplit('/').next().unwrap_or_default()
+}
+
+/// Matches text against a glob where * is any run of characters and ? any one character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last * was and how much text it has taken, to backtrack on a mismatch
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Routes next to shortcuts on the server, which can't be used as aliases
+const RESERVED_ALIASES: [&str; 8] = ["all", "audit", "batch", "healthz", "metrics", "readyz", "search", "trash"];
+
+/// Checks that an alias can be used as a hash: letters, digits, "-" and "_", up to 64 long
+pub fn is_valid_alias(alias: &str) -> bool {
+    (1..=64).contains(&alias.len())
+        && alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && !RESERVED_ALIASES.contains(&alias.to_lowercase().as_str())
+}
+
+/// Trims and lowercases tags, dropping empty and repeated ones
+pub fn normalize_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut tags = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Unix time in seconds as an RFC 3339 timestamp, e.g. 2024-05-01T12:00:00Z
+pub fn format_time(secs: u64) -> String {
+    OffsetDateTime::from_unix_timestamp(secs as i64)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| secs.to_string())
+}
+
 /// Generates hash for link, roughly 2.5 million permutations
 pub fn gen_hash() -> String {
     let word_len_1 = rand::thread_rng().gen_range(5..=7);
@@ -24,13 +383,207 @@ pub fn gen_hash() -> String {
     )
 }
 
-pub fn is_url(url: &str) -> bool {
-    const REGEX: &str = r"https?://(www\.)?[-a-zA-Z0-9@:%._\+~#=]{2,256}\.[a-z]{2,4}\b([-a-zA-Z0-9@:%_\+.~#?&//=]*)";
-    regex::Regex::new(REGEX).unwrap().is_match(url)
+/// Normalizes a link given by the user, validating it as a template if it has placeholders
+pub fn normalize_link(link: &str, strip_tracking: bool) -> Result<String, UrlError> {
+    match is_template(link) {
+        true => normalize_template(link, strip_tracking),
+        false => normalize_url(link, strip_tracking),
+    }
+}
+
+/// Parses the link into a canonical form, adding "https://" if no scheme is given.
+/// Hosts are lowercased and punycoded and default ports are dropped by the parser.
+pub fn normalize_url(url: &str, strip_tracking: bool) -> Result<String, UrlError> {
+    let with_scheme = add_scheme(url);
+
+    let mut parsed = Url::parse(&with_scheme).map_err(|e| UrlError::ParseError(e.to_string()))?;
+
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(UrlError::SchemeError(parsed.scheme().to_string()));
+    }
+    if parsed.host_str().is_none_or(|h| h.is_empty()) {
+        return Err(UrlError::HostError());
+    }
+
+    if strip_tracking && parsed.query().is_some() {
+        let kept: Vec<(String, String)> = parsed
+            .query_pairs()
+            .filter(|(k, _)| !is_tracking_param(k))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        if kept.is_empty() {
+            parsed.set_query(None);
+        } else {
+            parsed.query_pairs_mut().clear().extend_pairs(kept);
+        }
+    }
+
+    Ok(parsed.to_string())
+}
+
+/// Appends the remaining path segments to the link and merges in the query parameters,
+/// with parameters from the request replacing those of the same name on the link
+pub fn forward_url(link: &str, rest: Option<&str>, query: Option<&str>) -> Result<String, UrlError> {
+    let mut url = Url::parse(link).map_err(|e| UrlError::ParseError(e.to_string()))?;
+
+    if let Some(rest) = rest.filter(|r| !r.is_empty()) {
+        url.path_segments_mut()
+            .map_err(|_| UrlError::HostError())?
+            .pop_if_empty()
+            .extend(rest.split('/'));
+    }
+
+    if let Some(query) = query.filter(|q| !q.is_empty()) {
+        let incoming: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+        let existing: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| !incoming.iter().any(|(key, _)| key == k))
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(existing)
+            .extend_pairs(incoming);
+    }
+
+    Ok(url.to_string())
+}
+
+/// Checks if the link has placeholders such as {0} or {name}. Other braces, like those
+/// of JSON in a query string, don't make a template
+pub fn is_template(link: &str) -> bool {
+    link.split('{')
+        .skip(1)
+        .any(|part| part.split_once('}').is_some_and(|(name, _)| is_placeholder_name(name)))
+}
+
+/// Validates a templated link, only allowing placeholders after the host, and normalizes
+/// everything around them like [`normalize_url`]
+pub fn normalize_template(link: &str, strip_tracking: bool) -> Result<String, UrlError> {
+    let with_scheme = add_scheme(link);
+    let placeholders = template_placeholders(&with_scheme)?;
+
+    if placeholders.is_empty() {
+        return Err(UrlError::TemplateError("no placeholders given".to_string()));
+    }
+
+    let after_scheme = with_scheme.split_once("://").map_or("", |(_, rest)| rest);
+    let authority = after_scheme.split(['/', '?', '#']).next().unwrap_or("");
+    if authority.contains('{') {
+        return Err(UrlError::TemplateError(
+            "placeholders cannot be used in the host".to_string(),
+        ));
+    }
+
+    // Placeholders are swapped for markers the parser leaves as they are, then put back
+    let marker = (0..)
+        .map(|n| format!("tmpl{n}x"))
+        .find(|m| !with_scheme.contains(m.as_str()))
+        .unwrap_or_default();
+    let mut marked = with_scheme.clone();
+    for (i, name) in placeholders.iter().enumerate() {
+        marked = marked.replacen(&format!("{{{name}}}"), &format!("{marker}{i}{marker}"), 1);
+    }
+
+    let mut normalized = normalize_url(&marked, strip_tracking)?;
+    for (i, name) in placeholders.iter().enumerate() {
+        normalized = normalized.replacen(&format!("{marker}{i}{marker}"), &format!("{{{name}}}"), 1);
+    }
+
+    Ok(normalized)
+}
+
+/// Substitutes positional ({0}, {1}, ...) and named ({name}) placeholders with
+/// percent-encoded values
+pub fn expand_template(
+    link: &str,
+    positional: &[&str],
+    named: &[(String, String)],
+) -> Result<String, UrlError> {
+    let mut expanded = String::with_capacity(link.len());
+    let mut rest = link;
+
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| UrlError::TemplateError("unclosed placeholder".to_string()))?;
+        let name = &rest[start + 1..start + end];
+
+        let value = match name.parse::<usize>() {
+            Ok(index) => positional.get(index).copied(),
+            Err(_) => named.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str()),
+        }
+        .ok_or_else(|| UrlError::TemplateError(format!("missing parameter {name}")))?;
+
+        expanded.push_str(&rest[..start]);
+        expanded.extend(utf8_percent_encode(value, TEMPLATE_VALUE));
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+fn template_placeholders(link: &str) -> Result<Vec<&str>, UrlError> {
+    let mut placeholders = Vec::new();
+    let mut rest = link;
+
+    loop {
+        let open = rest.find('{');
+        let close = rest.find('}');
+        match (open, close) {
+            (None, None) => return Ok(placeholders),
+            (Some(start), Some(end)) if start < end => {
+                let name = &rest[start + 1..end];
+                if !is_placeholder_name(name) {
+                    return Err(UrlError::TemplateError(format!(
+                        "invalid placeholder {{{name}}}"
+                    )));
+                }
+                placeholders.push(name);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                return Err(UrlError::TemplateError(
+                    "unbalanced braces in link".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Adds "https://" unless the link has a scheme of its own, such as mailto:, which is then
+/// left for the parser to reject. A host followed by a port, e.g. localhost:3000, is not
+/// taken for a scheme
+fn add_scheme(url: &str) -> String {
+    let url = url.trim();
+    let has_scheme = url.split_once(':').is_some_and(|(scheme, rest)| {
+        let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+        let port_len = rest.chars().take_while(char::is_ascii_digit).count();
+        let is_port = port_len > 0 && rest[port_len..].chars().next().is_none_or(|c| matches!(c, '/' | '?' | '#'));
+        is_scheme && !is_port
+    });
+    match has_scheme || url.starts_with('/') {
+        true => url.to_string(),
+        false => format!("https://{url}"),
+    }
+}
+
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_") || TRACKING_PARAMS.contains(&key)
 }
 
 #[test]
-fn test_is_url() {
+fn test_valid_url() {
     let ok_urls = [
         "http://foo.com/blah_blah",
         "http://foo.com/blah_blah",
@@ -42,12 +595,199 @@ fn test_is_url() {
         "http://code.google.com/events/#&product=browser",
     ];
 
-    let bad_urls = ["http://", "//a", "foo.com", "h://test"];
+    let bad_urls = ["http://", "//a", "h://test"];
 
     for url in ok_urls.iter() {
-        assert_eq!(is_url(url), true);
+        assert!(normalize_url(url, false).is_ok());
     }
     for url in bad_urls.iter() {
-        assert_eq!(is_url(url), false);
+        assert!(normalize_url(url, false).is_err());
+    }
+}
+
+#[test]
+fn test_normalize_url() {
+    let cases = [
+        ("foo.com", "https://foo.com/"),
+        ("HTTP://Example.COM:80/Path", "http://example.com/Path"),
+        ("https://example.com:443/", "https://example.com/"),
+        ("https://www.example.museum", "https://www.example.museum/"),
+        ("http://192.168.1.10:8080/a", "http://192.168.1.10:8080/a"),
+        ("localhost:3000", "https://localhost:3000/"),
+        ("https://bücher.example", "https://xn--bcher-kva.example/"),
+    ];
+    for (url, expected) in cases.iter() {
+        assert_eq!(normalize_url(url, false).unwrap(), *expected);
+    }
+
+    assert_eq!(
+        normalize_url("https://example.com/?utm_source=x&id=4&fbclid=y", true).unwrap(),
+        "https://example.com/?id=4"
+    );
+    assert_eq!(
+        normalize_url("https://example.com/?utm_source=x", true).unwrap(),
+        "https://example.com/"
+    );
+    assert_eq!(
+        normalize_url("https://example.com/?utm_source=x", false).unwrap(),
+        "https://example.com/?utm_source=x"
+    );
+
+    assert_eq!(normalize_url("example.com:8080/a", false).unwrap(), "https://example.com:8080/a");
+    for url in ["http://", "ftp://example.com", "h://test", "", "mailto:x@y.com", "javascript:alert(1)"].iter() {
+        assert!(normalize_url(url, false).is_err());
+    }
+}
+
+#[test]
+fn test_forward_url() {
+    let cases = [
+        ("https://github.com/", Some("org/repo"), None, "https://github.com/org/repo"),
+        ("https://example.com/docs", Some("getting-started"), Some("ref=x"), "https://example.com/docs/getting-started?ref=x"),
+        ("https://example.com/?a=1&b=2", None, Some("b=3&c=4"), "https://example.com/?a=1&b=3&c=4"),
+        ("https://example.com/", Some("a b"), None, "https://example.com/a%20b"),
+        ("https://example.com/", Some(""), Some(""), "https://example.com/"),
+    ];
+    for (link, rest, query, expected) in cases.iter() {
+        assert_eq!(forward_url(link, *rest, *query).unwrap(), *expected);
+    }
+}
+
+#[test]
+fn test_templates() {
+    assert_eq!(
+        normalize_link("jira.example.com/browse/{0}", false).unwrap(),
+        "https://jira.example.com/browse/{0}"
+    );
+    assert_eq!(
+        expand_template("https://jira.example.com/browse/{0}", &["ABC-123"], &[]).unwrap(),
+        "https://jira.example.com/browse/ABC-123"
+    );
+    assert_eq!(
+        expand_template(
+            "https://example.com/{0}?q={query}",
+            &["a/b"],
+            &[("query".to_string(), "x y&z".to_string())]
+        )
+        .unwrap(),
+        "https://example.com/a%2Fb?q=x%20y%26z"
+    );
+    assert!(expand_template("https://example.com/{1}", &["a"], &[]).is_err());
+
+    // Hosts are normalized around the placeholders
+    assert_eq!(
+        normalize_link("https://Bücher.example/{0}?q={query}", false).unwrap(),
+        "https://xn--bcher-kva.example/{0}?q={query}"
+    );
+
+    // Braces that aren't placeholders leave the link an ordinary one
+    for link in ["https://example.com/{}", "https://example.com/?q={a-b}", "https://example.com/}0{"] {
+        assert!(!is_template(link));
+        assert!(normalize_link(link, false).is_ok());
+    }
+    assert!(is_template("https://example.com/{user_1}"));
+
+    let bad_templates = [
+        "https://{0}.example.com/",
+        "https://example.com/{0}/{1",
+        "https://example.com/{0}/}",
+        "https://example.com/{0}/{a-b}",
+    ];
+    for link in bad_templates.iter() {
+        assert!(normalize_link(link, false).is_err());
+    }
+}
+
+#[test]
+fn test_aliases() {
+    for alias in ["release-1.2", "", "a/b", "all", "Metrics", "audit", "audit#docs", "über"] {
+        assert!(!is_valid_alias(alias));
+    }
+    for alias in ["release_1-2", "docs", "a", "ALL-links"] {
+        assert!(is_valid_alias(alias));
     }
+    assert!(!is_valid_alias(&"a".repeat(65)));
+}
+
+#[test]
+fn test_shortcut_matching() {
+    for shortcut in [
+        "happy-cat",
+        "http://127.0.0.1:8080/go/happy-cat",
+        "http://127.0.0.1:8080/go/happy-cat/",
+        "http://127.0.0.1:8080/happy-cat?utm_source=x",
+        " happy-cat#top ",
+    ] {
+        assert_eq!(shortcut_hash(shortcut), "happy-cat");
+    }
+
+    assert!(glob_match("release-*", "release-1-0"));
+    assert!(glob_match("*-cat", "happy-cat"));
+    assert!(glob_match("h?ppy-*t", "happy-cat"));
+    assert!(glob_match("*a*a*", "banana"));
+    assert!(glob_match("*", ""));
+    assert!(!glob_match("release-*", "pre-release-1"));
+    assert!(!glob_match("h?ppy", "happy-cat"));
+    assert!(!glob_match("*a*x", "banana"));
+}
+
+#[test]
+fn test_delete_filter() {
+    assert!(DeleteFilter::default().is_unfiltered());
+    let filter = DeleteFilter {
+        all: true,
+        ..DeleteFilter::default()
+    };
+    assert_eq!(filter.route(), "all?all=true");
+    assert!(serde_json::from_str::<DeleteFilter>(r#"{"older_than": 30}"#).is_err());
+}
+
+#[test]
+fn test_tags() {
+    assert_eq!(normalize_tags([" Infra", "web", "", "infra"]), ["infra", "web"]);
+    let filter = ListFilter {
+        tag: Some("web,Infra".to_string()),
+    };
+    assert_eq!(filter.tags(), ["infra", "web"]);
+    assert_eq!(filter.route(), "all?tag=web%2CInfra");
+}
+
+#[test]
+fn test_audit_filter() {
+    assert_eq!(format_time(1_714_564_800), "2024-05-01T12:00:00Z");
+
+    assert_eq!(AuditFilter::default().route(), "audit");
+    let filter = AuditFilter {
+        hash: Some("happy-cat".to_string()),
+    };
+    assert_eq!(filter.route(), "audit?hash=happy-cat");
+}
+
+#[test]
+fn test_search_query() {
+    let query = SearchQuery {
+        q: "kube docs".to_string(),
+        limit: Some(5),
+    };
+    assert_eq!(query.route(), "search?q=kube+docs&limit=5");
+}
+
+#[test]
+fn test_passwords() {
+    let password_hash = hash_password("hunter2").unwrap();
+    assert!(verify_password("hunter2", &password_hash));
+    assert!(!verify_password("hunter3", &password_hash));
+    assert!(!verify_password("hunter2", "not-a-hash"));
+
+    let attempts = FailedAttempts::default();
+    let ip = IpAddr::from([127, 0, 0, 1]);
+    for _ in 0..MAX_PASSWORD_ATTEMPTS {
+        assert!(!attempts.is_locked(ip, "hello-world"));
+        attempts.record_failure(ip, "hello-world");
+    }
+    assert!(attempts.is_locked(ip, "hello-world"));
+    assert!(!attempts.is_locked(ip, "other-hash"));
+
+    attempts.clear(ip, "hello-world");
+    assert!(!attempts.is_locked(ip, "hello-world"));
 }